@@ -1,35 +1,125 @@
 #[allow(unused_imports)]
 use clap::Parser;
+use std::net::{SocketAddr, SocketAddrV4};
 
-use crate::iterators::{LoopIterator, PortRange, ScanType};
-use crate::iterators::{ports, scans};
+use crate::{dns, DNS_DEFAULT_TIMEOUT_MS};
+use crate::iterators::{LoopIterator, PortRange, ScanType, Shard};
+use crate::iterators::{permutation, ports, scans, top_ports};
+use crate::probes::{ftp, idle};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
 #[command(arg_required_else_help(true))]
 pub struct Args {
-	/// Addresses to scan or path to a file containing those addresses (can be hostnames too)
+	/// Addresses to scan or path to a file containing those addresses (can be hostnames, IPv4 or IPv6 CIDR blocks or octet ranges like 192.168.1-3.1,10-20)
+	/// "-" reads targets from stdin while scanning
 	#[arg(short, long)]
 	pub ip: Vec<String>,
 
-	/// Range of ports to scan
+	/// Output of a previous scan (nmap XML, masscan JSON or list, or this tool's JSON output)
+	/// hosts are only probed on the ports that were found open
+	#[arg(long)]
+	pub import: Vec<String>,
+
+	/// Write results to a file, one JSON object per port
+	#[arg(short, long)]
+	pub output: Option<String>,
+
+	/// Scan every IPv4 and IPv6 address a hostname resolves to instead of the first one only (IPv4 first)
+	#[arg(long)]
+	pub resolve_all: bool,
+
+	/// DNS servers to use instead of the system resolver (comma separated, port 53 by default)
+	#[arg(long, value_delimiter = ',', value_parser = dns::parse_server)]
+	pub dns_servers: Vec<SocketAddr>,
+
+	/// Time to wait for a DNS answer before asking the next server, in milliseconds
+	#[arg(long, default_value_t = DNS_DEFAULT_TIMEOUT_MS)]
+	pub dns_timeout: u64,
+
+	/// Look up the PTR record of hosts that answered
+	#[arg(short = 'R', long)]
+	pub reverse_dns: bool,
+
+	/// Addresses, hostnames, CIDR blocks or octet ranges that must never be scanned
+	#[arg(long)]
+	pub exclude: Vec<String>,
+
+	/// Path to a file containing targets that must never be scanned
+	#[arg(long)]
+	pub exclude_file: Vec<String>,
+
+	/// Also scan multicast, reserved and other special-use addresses, as well as network and broadcast addresses of CIDR blocks
+	#[arg(long)]
+	pub allow_special: bool,
+
+	/// Ports to scan, like 22,80,8000-8100 or service names like ssh,https, missing bounds default to 1 and 65535 so "-p-" scans every port
+	/// T:, U: or S: restrict the ports after them to TCP, UDP or SCTP scans, like T:1-1024,U:53,161, P: gives IP protocol numbers to PROTO scans
 	#[arg(short, long, default_value_t = LoopIterator::<PortRange>::default(), value_parser = ports::Parser)]
 	pub ports: LoopIterator<PortRange>,
 
-	/// Scan types
+	/// Scan the N most frequently open TCP and UDP ports instead of --ports, as ranked by Nmap (up to 1000 TCP and 100 UDP ports)
+	#[arg(long, conflicts_with = "ports")]
+	pub top_ports: Option<usize>,
+
+	/// Scan TCP and UDP ports found open at least this often, between 0 and 1 (the first --top-ports of them if given too)
+	#[arg(long, conflicts_with = "ports", value_parser = top_ports::parse_ratio)]
+	pub port_ratio: Option<f64>,
+
+	/// Ports that must never be probed, same format as --ports
+	#[arg(long, value_parser = ports::Parser)]
+	pub exclude_ports: Option<LoopIterator<PortRange>>,
+
+	/// Scan types among SYN, NULL, ACK, FIN, XMAS, UDP, WINDOW, MAIMON, CUSTOM (see --scanflags), INIT and COOKIE (SCTP INIT and COOKIE ECHO), PROTO (IP protocols, IPv4 only), IDLE (through --zombie, IPv4 only), BOUNCE (through --ftp-relay, IPv4 only) and CONNECT (which needs no raw socket, and is used instead of the others when raw sockets are not allowed)
 	#[arg(short, long, default_value_t = LoopIterator::<ScanType>::default(), value_parser = scans::Parser)]
 	pub scans: LoopIterator<ScanType>,
 
+	/// TCP flags sent by CUSTOM scans, like URGPSHFIN, SYN,ECE or a number like 0x29
+	#[arg(long, value_parser = scans::parse_flags)]
+	pub scanflags: Option<u16>,
+
+	/// How CUSTOM scans read ports that don't answer
+	#[arg(long, value_enum, default_value_t)]
+	pub scanflags_silence: scans::Silence,
+
+	/// How CUSTOM scans read ports that answer with a RST
+	#[arg(long, value_enum, default_value_t)]
+	pub scanflags_rst: scans::Reset,
+
+	/// Host IDLE scans send probes in the name of, with the port its IP-ID is read from (80 by default), like 10.0.0.9:443
+	/// it must not be sending anything else, and must number its packets one after the other
+	#[arg(long, value_parser = idle::parse_zombie)]
+	pub zombie: Option<SocketAddrV4>,
+
+	/// FTP server BOUNCE scans go through, as [user[:password]@]host[:port] (anonymous login and port 21 by default)
+	/// it must accept PORT commands with addresses other than ours
+	#[arg(long, value_parser = ftp::parse_relay)]
+	pub ftp_relay: Option<ftp::Relay>,
+
+	/// Send probes in a random order instead of sweeping hosts, scans and ports in sequence
+	#[arg(long)]
+	pub randomize: bool,
+
+	/// Key of the random order, the same seed gives the same order (implies --randomize)
+	#[arg(long)]
+	pub seed: Option<u64>,
+
+	/// Only send the N-th of M slices of the probes, e.g. 2/3 (every shard must be given the same targets, ports, scans and seed)
+	#[arg(long, default_value_t = Shard::default(), value_parser = permutation::parse_shard)]
+	pub shard: Shard,
+
 	/// Number of scans to run concurrently
 	#[arg(short, long, default_value_t = 1)]
 	pub threads: u8
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod test {
-	use crate::iterators::{PortRange, ScanType};
+	use crate::iterators::{ports, scans, PortRange, Protocol, ScanType};
 	use super::Args;
 	use clap::Parser;
+	use std::net::SocketAddr;
 
 	#[test]
 	fn port_basic_usage() {
@@ -72,13 +162,105 @@ mod test {
 	}
 	
 	#[test]
-	fn port_range_too_big() {
-		let arguments = vec![clap::crate_name!(), "-p 1025-1"];
+	fn port_range_full() {
+		for spec in ["-p-", "-p 1-65535", "-p 1025-1,-1024,1000-", "-p -"] {
+			match Args::try_parse_from(vec![clap::crate_name!(), spec]) {
+				Ok(args) => assert_eq!(&args.ports, &vec![PortRange::new(1, u16::MAX)]),
+				Err(e) => panic!("Parsing \"{spec}\" failed: {e}"),
+			};
+		}
 
+		match Args::try_parse_from(vec![clap::crate_name!(), "-p 80,8000-"]) {
+			Ok(args) => assert_eq!(&args.ports, &vec![PortRange::new(80, 80), PortRange::new(8000, u16::MAX)]),
+			Err(_) => assert!(false, "Parsing failed !"),
+		};
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "-p 0-"]).is_err());
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "-p -x"]).is_err());
+	}
+
+	#[test]
+	fn port_service_names() {
+		match Args::try_parse_from(vec![clap::crate_name!(), "-p ssh,http,https,postgresql,8000-8100"]) {
+			Ok(args) => assert_eq!(&args.ports, &vec![
+				PortRange::new(22, 22),
+				PortRange::new(80, 80),
+				PortRange::new(443, 443),
+				PortRange::new(5432, 5432),
+				PortRange::new(8000, 8100)
+			]),
+			Err(e) => panic!("Parsing failed: {e}"),
+		};
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "-p no-such-service"]).is_err());
+	}
+
+	#[test]
+	fn port_protocol_qualifiers() {
+		match Args::try_parse_from(vec![clap::crate_name!(), "-p 1-10,T:1-1024,U:53,123,snmp,8-12,s:99"]) {
+			Ok(args) => {
+				let ranges: Vec<String> = args.ports.as_slice().iter().map(|range| range.to_string()).collect();
+				assert_eq!(ranges, ["1-10", "T:11-1024", "U:11-12", "U:53-53", "U:123-123", "U:161-161", "S:99-99"]);
+				assert!(args.ports.as_slice()[1].applies_to(Protocol::Tcp));
+				assert!(!args.ports.as_slice()[1].applies_to(Protocol::Udp));
+				assert!(args.ports.as_slice()[0].applies_to(Protocol::Sctp));
+			},
+			Err(e) => panic!("Parsing failed: {e}"),
+		};
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "-p X:80"]).is_err());
+	}
+
+	#[test]
+	fn port_top_ports() {
+		match Args::try_parse_from(vec![clap::crate_name!(), "--top-ports=10", "--port-ratio=0.1"]) {
+			Ok(args) => {
+				assert_eq!(args.top_ports, Some(10));
+				assert_eq!(args.port_ratio, Some(0.1));
+			},
+			Err(e) => panic!("Parsing failed: {e}"),
+		};
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "--top-ports=10", "-p80"]).is_err());
+		assert!(Args::try_parse_from(vec![clap::crate_name!(), "--port-ratio=2"]).is_err());
+	}
+
+	#[test]
+	fn port_exclusions() {
+		let arguments = vec![clap::crate_name!(), "-p 1-1024,U:5000-6000", "--exclude-ports=22,T:80,U:5353,S:100-2000"];
 		match Args::try_parse_from(arguments) {
-			Ok(_) => assert!(false, "Parsing should have failed !"),
-			Err(e) => assert_eq!(e.kind(), clap::error::ErrorKind::InvalidValue),
+			Ok(args) => {
+				let ranges = ports::exclude(args.ports.as_slice(), args.exclude_ports.unwrap().as_slice());
+				let ranges: Vec<String> = ranges.iter().map(|range| range.to_string()).collect();
+				assert_eq!(ranges, [
+					"T:1-21", "T:23-79", "T:81-1024",
+					"U:1-21", "U:23-1024", "U:5000-5352", "U:5354-6000",
+					"S:1-21", "S:23-99",
+					"P:1-21", "P:23-1024"
+				]);
+			},
+			Err(e) => panic!("Parsing failed: {e}"),
 		};
+
+		let ranges = ports::exclude(&[PortRange::new(1, 10)], &[PortRange::new(1, 10)]);
+		assert!(ranges.is_empty());
+	}
+
+	#[test]
+	fn custom_scan_flags() {
+		let arguments = vec![clap::crate_name!(), "-s CUSTOM", "--scanflags=URGPSHFIN", "--scanflags-silence=filtered", "--scanflags-rst=unfiltered"];
+		match Args::try_parse_from(arguments) {
+			Ok(args) => {
+				assert_eq!(args.scanflags, Some(0x29));
+				assert_eq!(args.scanflags_silence, scans::Silence::Filtered);
+				assert_eq!(args.scanflags_rst, scans::Reset::Unfiltered);
+			},
+			Err(e) => panic!("Parsing failed: {e}"),
+		};
+
+		assert_eq!(scans::parse_flags("syn,ece"), Ok(0x42));
+		assert_eq!(scans::parse_flags("NSCWR"), Ok(0x180));
+		assert_eq!(scans::parse_flags("0x12"), Ok(0x12));
+		assert_eq!(scans::parse_flags("0"), Ok(0));
+		assert!(scans::parse_flags("SYNX").is_err());
+		assert!(scans::parse_flags("").is_err());
+		assert!(scans::parse_flags("0x200").is_err());
 	}
 
 	#[test]
@@ -101,6 +283,24 @@ mod test {
 		};
 	}
 
+	#[test]
+	fn dns_servers() {
+		let arguments = vec![clap::crate_name!(), "--dns-servers=10.0.0.53, 10.0.0.54:5353", "--dns-servers=::1"];
+		let args = Args::try_parse_from(arguments).unwrap();
+
+		assert_eq!(args.dns_servers, vec![
+			SocketAddr::from(([10, 0, 0, 53], 53)),
+			SocketAddr::from(([10, 0, 0, 54], 5353)),
+			SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 53))
+		]);
+
+		let arguments = vec![clap::crate_name!(), "--dns-servers=dns.internal"];
+		match Args::try_parse_from(arguments) {
+			Ok(_) => assert!(false, "Parsing should have failed !"),
+			Err(e) => assert_eq!(e.kind(), clap::error::ErrorKind::ValueValidation),
+		};
+	}
+
 	#[test]
 	fn scan_basic_usage() {
 		let arguments = vec![clap::crate_name!(), "-s SYN,null,XMAS,syn"];
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use mio::{Poll, Events, Token, Interest, net::UdpSocket};
+
+use std::sync::Arc;
+use crate::{cli, DNS_MAX_INFLIGHT, DNS_PORT};
+
+/*
+** Minimal DNS client over UDP
+** queries are sent in parallel to the configured servers
+** and retried on the next server when one does not answer
+*/
+#[derive(Clone, Debug)]
+pub struct Client {
+	servers: Vec<SocketAddr>,
+	timeout: Duration
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueryType {
+	A = 1,
+	Ptr = 12,
+	Aaaa = 28
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Record {
+	A(Ipv4Addr),
+	Aaaa(Ipv6Addr),
+	Ptr(String),
+	Other
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rcode(u8);
+
+impl Rcode {
+	const NOERROR: Rcode = Rcode(0);
+	const SERVFAIL: Rcode = Rcode(2);
+	const REFUSED: Rcode = Rcode(5);
+}
+
+impl Display for Rcode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.0 {
+			0 => write!(f, "NOERROR"),
+			1 => write!(f, "FORMERR"),
+			2 => write!(f, "SERVFAIL"),
+			3 => write!(f, "NXDOMAIN"),
+			4 => write!(f, "NOTIMP"),
+			5 => write!(f, "REFUSED"),
+			code => write!(f, "RCODE{code}")
+		}
+	}
+}
+
+// Built-in client configured from the command line
+// None means the system resolver should be used
+pub fn client(options: &cli::Args) -> Option<Arc<Client>> {
+	if options.dns_servers.is_empty() {
+		return None;
+	}
+
+	Some(Arc::new(Client::new(options.dns_servers.clone(), Duration::from_millis(options.dns_timeout))))
+}
+
+// Accepts "10.0.0.53", "10.0.0.53:5353", "::1" or "[::1]:5353"
+pub fn parse_server(str: &str) -> Result<SocketAddr, String> {
+	let str = str.trim();
+	if let Ok(addr) = str.parse::<SocketAddr>() {
+		return Ok(addr);
+	}
+
+	match str.parse::<std::net::IpAddr>() {
+		Ok(ip) => Ok((ip, DNS_PORT).into()),
+		Err(_) => Err(format!("\"{str}\" is not a valid DNS server address"))
+	}
+}
+
+// "10.2.3.4" becomes "4.3.2.10.in-addr.arpa"
+// IPv6 addresses are reversed nibble by nibble under "ip6.arpa"
+pub fn ptr_name(addr: IpAddr) -> String {
+	match addr {
+		IpAddr::V4(addr) => {
+			let [a, b, c, d] = addr.octets();
+			format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+		},
+		IpAddr::V6(addr) => {
+			let nibbles: String = addr.octets().iter().rev().map(|byte| format!("{:x}.{:x}.", byte & 0xf, byte >> 4)).collect();
+			format!("{nibbles}ip6.arpa")
+		}
+	}
+}
+
+fn build_query(id: u16, name: &str, qtype: QueryType) -> Result<Vec<u8>> {
+	let mut packet = Vec::with_capacity(18 + name.len());
+	packet.extend_from_slice(&id.to_be_bytes());
+	packet.extend_from_slice(&[0x01, 0x00]); // recursion desired
+	packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // one question
+
+	for label in name.trim_end_matches('.').split('.') {
+		if label.is_empty() || label.len() > 63 {
+			return Err(anyhow!("\"{name}\" is not a valid domain name"));
+		}
+		packet.push(label.len() as u8);
+		packet.extend_from_slice(label.as_bytes());
+	}
+	packet.push(0);
+
+	packet.extend_from_slice(&(qtype as u16).to_be_bytes());
+	packet.extend_from_slice(&[0, 1]); // class IN
+	Ok(packet)
+}
+
+// Reads a possibly compressed name starting at offset
+// returns the name and the offset right after it
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+	let mut labels: Vec<String> = vec![];
+	let mut end = None;
+	let mut jumps = 0;
+
+	loop {
+		let len = *packet.get(offset)? as usize;
+		match len {
+			0 => {
+				return Some((labels.join("."), end.unwrap_or(offset + 1)));
+			},
+			l if l & 0xc0 == 0xc0 => { // compression pointer
+				let pointer = ((l & 0x3f) << 8) | *packet.get(offset + 1)? as usize;
+				end.get_or_insert(offset + 2);
+				jumps += 1;
+				if jumps > 32 {
+					return None; // pointer loop
+				}
+				offset = pointer;
+			},
+			l => {
+				let label = packet.get(offset + 1..offset + 1 + l)?;
+				labels.push(String::from_utf8_lossy(label).into_owned());
+				offset += 1 + l;
+			}
+		}
+	}
+}
+
+struct Response {
+	id: u16,
+	rcode: Rcode,
+	truncated: bool,
+	question: Option<(String, u16)>, // name and type of the first question
+	records: Vec<Record>
+}
+
+impl Response {
+	fn answers(&self, name: &str, qtype: QueryType) -> bool {
+		self.question.as_ref().is_some_and(|(n, t)| n.eq_ignore_ascii_case(name.trim_end_matches('.')) && *t == qtype as u16)
+	}
+}
+
+// Parses a response into its header, question and answer records
+fn parse_response(packet: &[u8]) -> Option<Response> {
+	let header = packet.get(0..12)?;
+	let id = u16::from_be_bytes([header[0], header[1]]);
+	if header[2] & 0x80 == 0 {
+		return None; // not a response
+	}
+	let truncated = header[2] & 0x02 != 0;
+	let rcode = Rcode(header[3] & 0x0f);
+	let questions = u16::from_be_bytes([header[4], header[5]]);
+	let answers = u16::from_be_bytes([header[6], header[7]]);
+
+	let mut offset = 12;
+	let mut question = None;
+	for _ in 0..questions {
+		let (name, end) = read_name(packet, offset)?;
+		let qtype = packet.get(end..end + 2)?;
+		question.get_or_insert((name, u16::from_be_bytes([qtype[0], qtype[1]])));
+		offset = end + 4;
+	}
+
+	let mut records = vec![];
+	for _ in 0..answers {
+		offset = read_name(packet, offset)?.1;
+		let fixed = packet.get(offset..offset + 10)?;
+		let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+		let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+		offset += 10;
+
+		let rdata = packet.get(offset..offset + rdlength)?;
+		records.push(match rtype {
+			1 if rdlength == 4 => Record::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+			12 => Record::Ptr(read_name(packet, offset)?.0),
+			28 if rdlength == 16 => Record::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(rdata).unwrap())),
+			_ => Record::Other
+		});
+		offset += rdlength;
+	}
+
+	Some(Response { id, rcode, truncated, question, records })
+}
+
+struct Pending {
+	index: usize,
+	server: usize,
+	attempts: usize,
+	sent: Instant,
+	error: Option<String> // why the last try could not be sent
+}
+
+impl Pending {
+	// A query that could not be sent is a try that already timed out
+	fn sent(&mut self, result: std::io::Result<usize>, server: &SocketAddr, timeout: Duration) {
+		self.sent = Instant::now();
+		self.error = None;
+		if let Err(e) = result {
+			self.sent = self.sent.checked_sub(timeout).unwrap_or(self.sent);
+			self.error = Some(format!("could not send the query to {server}: {e}"));
+		}
+	}
+}
+
+impl Client {
+	pub fn new(servers: Vec<SocketAddr>, timeout: Duration) -> Self {
+		Self { servers, timeout }
+	}
+
+	// Resolves every name in parallel
+	// results are in the same order as names
+	pub fn query_many(&self, names: &[String], qtype: QueryType) -> Vec<Result<Vec<Record>>> {
+		let queries: Vec<(&str, QueryType)> = names.iter().map(|name| (name.as_str(), qtype)).collect();
+		self.query_each(&queries)
+	}
+
+	// Same with a record type for each name
+	pub fn query_each(&self, queries: &[(&str, QueryType)]) -> Vec<Result<Vec<Record>>> {
+		let mut results: Vec<Option<Result<Vec<Record>>>> = queries.iter().map(|_| None).collect();
+		if let Err(e) = self.run(queries, &mut results) {
+			for result in results.iter_mut().filter(|r| r.is_none()) {
+				*result = Some(Err(anyhow!("{e}")));
+			}
+		}
+
+		queries.iter().zip(results).map(|((name, _), result)| {
+			result.unwrap_or_else(|| Err(anyhow!("\"{name}\": no answer")))
+		}).collect()
+	}
+
+	pub fn query(&self, name: &str, qtype: QueryType) -> Result<Vec<Record>> {
+		self.query_many(&[name.to_string()], qtype).pop().unwrap()
+	}
+
+	fn run(&self, queries: &[(&str, QueryType)], results: &mut [Option<Result<Vec<Record>>>]) -> Result<()> {
+		if self.servers.is_empty() {
+			return Err(anyhow!("no DNS server to query"));
+		}
+
+		let mut poll = Poll::new()?;
+		let mut events = Events::with_capacity(64);
+		let mut sockets: [Option<UdpSocket>; 2] = [None, None]; // IPv4 and IPv6
+		for (i, ipv6) in [false, true].into_iter().enumerate() {
+			if !self.servers.iter().any(|s| s.is_ipv6() == ipv6) {
+				continue ;
+			}
+
+			let mut socket = match ipv6 {
+				false => UdpSocket::bind(([0u8; 4], 0).into())?,
+				true => UdpSocket::bind(([0u16; 8], 0).into())?
+			};
+			poll.registry().register(&mut socket, Token(i), Interest::READABLE)?;
+			sockets[i] = Some(socket);
+		}
+
+		let socket_for = |server: &SocketAddr| sockets[server.is_ipv6() as usize].as_ref().unwrap();
+		let max_attempts = self.servers.len() * crate::DNS_RETRIES;
+		let mut inflight: HashMap<u16, Pending> = HashMap::new();
+		let mut next = 0;
+		let buffer = &mut [0u8; 4096];
+
+		while next < queries.len() || !inflight.is_empty() {
+			// Keep the window full
+			while next < queries.len() && inflight.len() < DNS_MAX_INFLIGHT {
+				let mut id: u16 = rand::random();
+				while inflight.contains_key(&id) {
+					id = id.wrapping_add(1);
+				}
+
+				let (name, qtype) = queries[next];
+				match build_query(id, name, qtype) {
+					Ok(query) => {
+						let server = &self.servers[0];
+						let mut pending = Pending { index: next, server: 0, attempts: 1, sent: Instant::now(), error: None };
+						pending.sent(socket_for(server).send_to(&query, *server), server, self.timeout);
+						inflight.insert(id, pending);
+					},
+					Err(e) => results[next] = Some(Err(e))
+				};
+				next += 1;
+			}
+
+			let oldest = inflight.values().map(|p| p.sent).min();
+			let wait = oldest.map_or(Duration::ZERO, |sent| self.timeout.saturating_sub(sent.elapsed()));
+			poll.poll(&mut events, Some(wait))?;
+
+			for ev in events.iter() {
+				let socket = sockets[ev.token().0].as_ref().unwrap();
+				loop {
+					let (bytes, from) = match socket.recv_from(buffer) {
+						Ok(r) => r,
+						Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+						Err(e) => return Err(e.into())
+					};
+
+					let response = match parse_response(&buffer[..bytes]) {
+						Some(r) => r,
+						None => continue
+					};
+					let (id, rcode) = (response.id, response.rcode);
+					let pending = match inflight.get_mut(&id) {
+						Some(p) if self.servers[p.server] == from && response.answers(queries[p.index].0, queries[p.index].1) => p,
+						_ => continue // stale or spoofed answer
+					};
+
+					// A truncated answer is missing records, there is no TCP fallback so another server is tried
+					let (name, qtype) = queries[pending.index];
+					let server = self.servers[pending.server];
+					let failed = response.truncated || rcode == Rcode::SERVFAIL || rcode == Rcode::REFUSED;
+					if failed && pending.attempts < max_attempts {
+						pending.server = (pending.server + 1) % self.servers.len();
+						pending.attempts += 1;
+						let next_server = self.servers[pending.server];
+						pending.sent(socket_for(&next_server).send_to(&build_query(id, name, qtype)?, next_server), &next_server, self.timeout);
+						continue ;
+					}
+
+					let pending = inflight.remove(&id).unwrap();
+					results[pending.index] = Some(match rcode {
+						_ if response.truncated => Err(anyhow!("\"{name}\": truncated answer from {server}")),
+						Rcode::NOERROR => Ok(response.records),
+						rcode => Err(anyhow!("\"{name}\": {rcode} from {server}"))
+					});
+				}
+			}
+
+			// Retry timed out queries on the next server
+			let expired: Vec<u16> = inflight.iter()
+				.filter(|(_, p)| p.sent.elapsed() >= self.timeout)
+				.map(|(id, _)| *id)
+				.collect();
+			for id in expired.into_iter() {
+				let pending = inflight.get_mut(&id).unwrap();
+				let (name, qtype) = queries[pending.index];
+
+				if pending.attempts >= max_attempts {
+					let pending = inflight.remove(&id).unwrap();
+					results[pending.index] = Some(Err(match pending.error {
+						Some(error) => anyhow!("\"{name}\": {error}"),
+						None => anyhow!("\"{name}\": no answer within {}ms", self.timeout.as_millis())
+					}));
+					continue ;
+				}
+
+				pending.server = (pending.server + 1) % self.servers.len();
+				pending.attempts += 1;
+				let server = self.servers[pending.server];
+				pending.sent(socket_for(&server).send_to(&build_query(id, name, qtype)?, server), &server, self.timeout);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+	use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+	use std::time::Duration;
+	use super::{Client, QueryType, Record, build_query, parse_response, parse_server, ptr_name, read_name};
+
+	// Answers A queries for "a.test" and "many.test", AAAA queries for "many.test" and "v6.test",
+	// NXDOMAIN for "nx.test", SERVFAIL for "fail.test", a truncated answer for "tc.test",
+	// the question of another name for "spoof.test" and never answers anything else
+	pub(crate) fn fake_server() -> SocketAddr {
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let addr = socket.local_addr().unwrap();
+
+		std::thread::spawn(move || {
+			let buffer = &mut [0u8; 512];
+			loop {
+				let (bytes, from) = socket.recv_from(buffer).unwrap();
+				let query = &buffer[..bytes];
+				let (name, end) = read_name(query, 12).unwrap();
+
+				let mut response = query[..end + 4].to_vec();
+				response[2] |= 0x80;
+				let aaaa = query[end..end + 2] == [0, 28];
+				let answers: Vec<Vec<u8>> = match (name.as_str(), aaaa) {
+					("a.test", false) => vec![vec![10, 0, 0, 1]],
+					("many.test", false) => vec![vec![10, 0, 0, 2], vec![10, 0, 0, 3]],
+					("many.test" | "v6.test", true) => vec![vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]],
+					("a.test" | "v6.test", _) => vec![],
+					("nx.test", _) => {
+						response[3] |= 3;
+						vec![]
+					},
+					("fail.test", _) => {
+						response[3] |= 2;
+						vec![]
+					},
+					("tc.test", _) => {
+						response[2] |= 0x02;
+						vec![]
+					},
+					("spoof.test", _) => {
+						response[13] = b't'; // "spoof" becomes "tpoof"
+						vec![vec![10, 0, 0, 66]]
+					},
+					_ => continue
+				};
+
+				response[7] = answers.len() as u8;
+				for rdata in answers.iter() {
+					response.extend_from_slice(&[0xc0, 12, 0, if aaaa { 28 } else { 1 }, 0, 1, 0, 0, 0, 60, 0, rdata.len() as u8]);
+					response.extend_from_slice(rdata);
+				}
+				socket.send_to(&response, from).unwrap();
+			}
+		});
+
+		addr
+	}
+
+	#[test]
+	fn query_format() {
+		let query = build_query(0x1234, "db.internal", QueryType::A).unwrap();
+		assert_eq!(query, [
+			0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0,
+			2, b'd', b'b', 8, b'i', b'n', b't', b'e', b'r', b'n', b'a', b'l', 0,
+			0, 1, 0, 1
+		]);
+
+		assert!(build_query(1, "bad..name", QueryType::A).is_err());
+	}
+
+	#[test]
+	fn response_with_compression() {
+		let mut packet = build_query(7, "www.test", QueryType::A).unwrap();
+		packet[2] = 0x81;
+		packet[3] = 0x80;
+		packet[7] = 2;
+		// www.test CNAME web.test
+		packet.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3, b'w', b'e', b'b', 0xc0, 16]);
+		// web.test A 10.1.2.3
+		packet.extend_from_slice(&[0xc0, 38, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 1, 2, 3]);
+
+		let response = parse_response(&packet).unwrap();
+		assert_eq!(response.id, 7);
+		assert_eq!(response.rcode.to_string(), "NOERROR");
+		assert!(!response.truncated);
+		assert!(response.answers("www.test.", QueryType::A) && !response.answers("www.test", QueryType::Aaaa));
+		assert_eq!(response.records, vec![Record::Other, Record::A(Ipv4Addr::new(10, 1, 2, 3))]);
+		assert_eq!(read_name(&packet, 38).unwrap().0, "web.test");
+
+		assert!(parse_response(&packet[..packet.len() - 2]).is_none());
+	}
+
+	#[test]
+	fn server_addresses() {
+		assert_eq!(parse_server("10.0.0.53").unwrap(), SocketAddr::from(([10, 0, 0, 53], 53)));
+		assert_eq!(parse_server("10.0.0.53:5353").unwrap(), SocketAddr::from(([10, 0, 0, 53], 5353)));
+		assert_eq!(parse_server("::1").unwrap(), SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 53)));
+		assert_eq!(parse_server("[::1]:5353").unwrap(), SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 5353)));
+		assert!(parse_server("dns.internal").is_err());
+		assert_eq!(ptr_name(Ipv4Addr::new(10, 2, 3, 4).into()), "4.3.2.10.in-addr.arpa");
+		assert_eq!(
+			ptr_name("2001:db8::567:89ab".parse().unwrap()),
+			"b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+		);
+	}
+
+	#[test]
+	fn client_parallel_queries() {
+		let client = Client::new(vec![fake_server()], Duration::from_millis(100));
+		let names: Vec<String> = ["a.test", "nx.test", "many.test", "slow.test", "fail.test"].iter().map(|s| s.to_string()).collect();
+		let results = client.query_many(&names, QueryType::A);
+
+		assert_eq!(results[0].as_ref().unwrap(), &vec![Record::A(Ipv4Addr::new(10, 0, 0, 1))]);
+		assert!(results[1].as_ref().unwrap_err().to_string().starts_with("\"nx.test\": NXDOMAIN from 127.0.0.1:"));
+		assert_eq!(results[2].as_ref().unwrap().len(), 2);
+		assert_eq!(results[3].as_ref().unwrap_err().to_string(), "\"slow.test\": no answer within 100ms");
+		assert!(results[4].as_ref().unwrap_err().to_string().starts_with("\"fail.test\": SERVFAIL from 127.0.0.1:"));
+	}
+
+	#[test]
+	fn client_aaaa_queries() {
+		let client = Client::new(vec![fake_server()], Duration::from_millis(100));
+		let names: Vec<String> = ["v6.test", "a.test"].iter().map(|s| s.to_string()).collect();
+		let results = client.query_many(&names, QueryType::Aaaa);
+
+		assert_eq!(results[0].as_ref().unwrap(), &vec![Record::Aaaa("2001:db8::1".parse().unwrap())]);
+		assert!(results[1].as_ref().unwrap().is_empty());
+
+		let results = client.query_each(&[("many.test", QueryType::A), ("many.test", QueryType::Aaaa)]);
+		assert_eq!(results[0].as_ref().unwrap().len(), 2);
+		assert_eq!(results[1].as_ref().unwrap(), &vec![Record::Aaaa("2001:db8::1".parse().unwrap())]);
+	}
+
+	#[test]
+	fn client_unusable_answers() {
+		// Truncated answers are retried on every server before giving up
+		let client = Client::new(vec![fake_server(), fake_server()], Duration::from_millis(50));
+		assert!(client.query("tc.test", QueryType::A).unwrap_err().to_string().starts_with("\"tc.test\": truncated answer from 127.0.0.1:"));
+
+		// Answers to another question are ignored
+		assert_eq!(client.query("spoof.test", QueryType::A).unwrap_err().to_string(), "\"spoof.test\": no answer within 50ms");
+	}
+
+	#[test]
+	fn client_falls_back_to_next_server() {
+		let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let client = Client::new(vec![silent.local_addr().unwrap(), fake_server()], Duration::from_millis(50));
+
+		assert_eq!(client.query("a.test", QueryType::A).unwrap(), vec![Record::A(Ipv4Addr::new(10, 0, 0, 1))]);
+	}
+
+	#[test]
+	fn client_send_errors() {
+		// Broadcasts are refused without SO_BROADCAST
+		let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, 53));
+		let client = Client::new(vec![broadcast, fake_server()], Duration::from_millis(50));
+		let names: Vec<String> = ["a.test", "v6.test"].iter().map(|s| s.to_string()).collect();
+		let results = client.query_many(&names, QueryType::A);
+		assert_eq!(results[0].as_ref().unwrap(), &vec![Record::A(Ipv4Addr::new(10, 0, 0, 1))]);
+		assert!(results[1].as_ref().unwrap().is_empty());
+
+		let client = Client::new(vec![broadcast], Duration::from_millis(50));
+		assert!(client.query("a.test", QueryType::A).unwrap_err().to_string().starts_with("\"a.test\": could not send the query to 255.255.255.255:53: "));
+	}
+}
@@ -0,0 +1,682 @@
+use std::collections::{HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use anyhow::{Result, anyhow};
+
+use crate::{IPV6_MAX_PREFIX, OCTET_MAX_RANGES, STREAM_DEDUP_WINDOW};
+
+/*
+** Inclusive range of IPv4 or IPv6 addresses
+** addresses are generated on the fly so that
+** a whole /8 does not need to be stored in memory
+** both families are stored as integers, v6 tells them apart
+*/
+#[derive(Clone, Debug, Copy)]
+pub struct Range {
+	v6: bool,
+	start: u128,
+	current: Option<u128>,
+	end: u128
+}
+
+// Integer value of an address and whether it is IPv6
+fn bits(addr: IpAddr) -> (bool, u128) {
+	match addr {
+		IpAddr::V4(addr) => (false, u32::from(addr) as u128),
+		IpAddr::V6(addr) => (true, u128::from(addr))
+	}
+}
+
+// Ranges are compared by their bounds, however far they were iterated
+impl PartialEq for Range {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other).is_eq()
+	}
+}
+
+impl Eq for Range {}
+
+impl PartialOrd for Range {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Range {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		(self.v6, self.start, self.end).cmp(&(other.v6, other.start, other.end))
+	}
+}
+
+impl Range {
+	// Panics if start and end are not of the same family
+	pub fn new<A: Into<IpAddr>>(start: A, end: A) -> Self {
+		let ((v6, start), (end_v6, end)) = (bits(start.into()), bits(end.into()));
+		assert_eq!(v6, end_v6, "address range mixes IPv4 and IPv6");
+		Range { v6, start, current: Some(start), end }
+	}
+
+	fn from_bits(v6: bool, start: u128, end: u128) -> Self {
+		Range { v6, start, current: Some(start), end }
+	}
+
+	fn addr(&self, value: u128) -> IpAddr {
+		match self.v6 {
+			false => IpAddr::V4(Ipv4Addr::from(value as u32)),
+			true => IpAddr::V6(Ipv6Addr::from(value))
+		}
+	}
+
+	// IPv6 blocks are limited to IPV6_MAX_PREFIX so this never overflows
+	// unless a huge number of them are merged together
+	pub(crate) fn len(&self) -> u64 {
+		(self.end - self.start) as u64 + 1
+	}
+
+	pub fn is_ipv6(&self) -> bool {
+		self.v6
+	}
+
+	pub fn contains(&self, addr: IpAddr) -> bool {
+		let (v6, value) = bits(addr);
+		v6 == self.v6 && (self.start..=self.end).contains(&value)
+	}
+}
+
+impl super::Span for Range {
+	type Item = IpAddr;
+
+	fn size(&self) -> u64 {
+		self.len()
+	}
+
+	fn at(&self, offset: u64) -> IpAddr {
+		self.addr(self.start + offset as u128)
+	}
+}
+
+impl From<IpAddr> for Range {
+	fn from(addr: IpAddr) -> Self {
+		Range::new(addr, addr)
+	}
+}
+
+impl From<Ipv4Addr> for Range {
+	fn from(addr: Ipv4Addr) -> Self {
+		Range::new(addr, addr)
+	}
+}
+
+impl From<Ipv6Addr> for Range {
+	fn from(addr: Ipv6Addr) -> Self {
+		Range::new(addr, addr)
+	}
+}
+
+// Accepts a single address or a CIDR block like "10.0.0.0/16" or "2001:db8::/120"
+// host bits of the base address are ignored, as nmap does
+impl TryFrom<&str> for Range {
+	type Error = anyhow::Error;
+
+	fn try_from(str: &str) -> Result<Self, <Self as TryFrom<&str>>::Error> {
+		let str = str.trim();
+		let (addr, prefix) = match str.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (str, None)
+		};
+
+		let addr = addr.parse::<IpAddr>().map_err(|_| anyhow!("\"{addr}\" is not a valid IP address"))?;
+		let width = if addr.is_ipv6() { 128 } else { 32 };
+		let prefix = match prefix {
+			Some(p) => match p.parse::<u32>() {
+				Ok(p) if p <= width => p,
+				_ => return Err(anyhow!("\"{p}\" is not a valid CIDR prefix length in \"{str}\""))
+			},
+			None => return Ok(Range::from(addr))
+		};
+		if addr.is_ipv6() && prefix < IPV6_MAX_PREFIX {
+			return Err(anyhow!("\"{str}\" is too large, IPv6 blocks can't be wider than /{IPV6_MAX_PREFIX}"));
+		}
+
+		let (v6, value) = bits(addr);
+		let host = u128::MAX.checked_shr(prefix + 128 - width).unwrap_or(0);
+		Ok(Range::from_bits(v6, value & !host, value | host))
+	}
+}
+
+impl std::fmt::Display for Range {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self.start == self.end {
+			true => write!(f, "{}", self.addr(self.start)),
+			false => write!(f, "{}-{}", self.addr(self.start), self.addr(self.end))
+		}
+	}
+}
+
+impl Iterator for Range {
+	type Item = IpAddr;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		match self.current {
+			Some(addr) => {
+				if addr < self.end {
+					self.current = Some(addr + 1);
+				} else {
+					self.current = None;
+				}
+				Some(self.addr(addr))
+			},
+			None => None
+		}
+	}
+}
+
+// Characters allowed in an address, a CIDR block or an octet range
+// hostnames can't contain ':' so anything that does is an IPv6 address
+// anything else is considered to be a hostname
+pub fn is_spec(str: &str) -> bool {
+	let str = str.trim();
+	match str.contains(':') {
+		true => str.chars().all(|c| c.is_ascii_hexdigit() || ".:/".contains(c)),
+		false => str.chars().all(|c| c.is_ascii_digit() || ".-,*/".contains(c))
+	}
+}
+
+// Parses a target specification into address ranges
+// accepts an address, a CIDR block or nmap-style octet ranges ("192.168.1-3.10-20,30,40")
+// octet ranges are IPv4 only
+pub fn parse(str: &str) -> Result<Vec<Range>> {
+	let str = str.trim();
+	if str.contains(':') || !str.contains(['-', ',', '*']) {
+		return Ok(vec![Range::try_from(str)?]);
+	}
+
+	let parts: Vec<&str> = str.split('.').collect();
+	if parts.len() != 4 {
+		return Err(anyhow!("\"{str}\" must contain exactly 4 octets"));
+	}
+
+	let mut octets: Vec<Vec<(u8, u8)>> = vec![];
+	for (i, part) in parts.iter().enumerate() {
+		octets.push(parse_octet(part).map_err(|value| {
+			anyhow!("\"{value}\" is not valid in octet {} of \"{str}\"", i + 1)
+		})?);
+	}
+
+	// Trailing octets covering 0-255 form contiguous blocks
+	// so only the octets before them need to be expanded
+	let last = octets.iter().rposition(|o| o[..] != [(0, 255)]);
+	let mut result = vec![];
+	if let Some(last) = last {
+		let count = octets[..last].iter()
+			.map(|octet| octet.iter().map(|&(lo, hi)| (hi - lo) as u64 + 1).sum::<u64>())
+			.product::<u64>() * octets[last].len() as u64;
+		if count > OCTET_MAX_RANGES {
+			return Err(anyhow!("\"{str}\" expands to {count} separate blocks, no more than {OCTET_MAX_RANGES} are allowed"));
+		}
+
+		let shift = 8 * (3 - last) as u32;
+		let suffix = u32::MAX.checked_shl(shift).map_or(u32::MAX, |mask| !mask);
+		// Leading octets are combined on the fly rather than collected
+		let prefixes = octets[..last].iter().fold(Box::new(std::iter::once(0u32)) as Box<dyn Iterator<Item = u32>>, |prefixes, octet| {
+			Box::new(prefixes.flat_map(move |prefix| octet.iter().flat_map(move |&(lo, hi)| (lo..=hi).map(move |v| (prefix << 8) | v as u32))))
+		});
+
+		result.reserve(count as usize);
+		for prefix in prefixes {
+			for &(lo, hi) in octets[last].iter() {
+				let base = ((prefix << 8) as u64) << shift;
+				let start = base as u32 | ((lo as u32) << shift);
+				let end = base as u32 | ((hi as u32) << shift) | suffix;
+				result.push(Range::new(Ipv4Addr::from(start), Ipv4Addr::from(end)));
+			}
+		}
+	} else {
+		result.push(Range::new(Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST));
+	}
+
+	Ok(result)
+}
+
+// Parses a comma separated list of values and ranges for a single octet
+// "*" and "-" stand for 0-255, bounds of a range can be omitted ("-10", "250-")
+// on error, returns the faulty part
+fn parse_octet(str: &str) -> Result<Vec<(u8, u8)>, String> {
+	let mut values: Vec<(u8, u8)> = vec![];
+
+	for item in str.split(',') {
+		let value = |v: &str, default: u8| match v.trim() {
+			"" => Ok(default),
+			v => v.parse::<u8>().map_err(|_| item.to_string())
+		};
+
+		let (lo, hi) = match item.trim() {
+			"" => return Err(item.to_string()),
+			"*" => (0, 255),
+			i => match i.split_once('-') {
+				Some((lo, hi)) => (value(lo, 0)?, value(hi, 255)?),
+				None => {
+					let v = value(i, 0)?;
+					(v, v)
+				}
+			}
+		};
+
+		if lo > hi {
+			return Err(item.to_string());
+		}
+		values.push((lo, hi));
+	}
+
+	values.sort();
+	let mut merged: Vec<(u8, u8)> = vec![];
+	for (lo, hi) in values.into_iter() {
+		match merged.last_mut() {
+			Some(last) if lo as u16 <= last.1 as u16 + 1 => last.1 = last.1.max(hi),
+			_ => merged.push((lo, hi))
+		}
+	}
+
+	Ok(merged)
+}
+
+// Sort ranges and merge the ones that overlap or touch
+// so that every address is generated exactly once
+pub fn merge(mut array: Vec<Range>) -> Vec<Range> {
+	let mut result: Vec<Range> = vec![];
+
+	array.sort_by_key(|r| (r.v6, r.start, r.end));
+	for range in array.into_iter() {
+		match result.last_mut() {
+			Some(last) if last.v6 == range.v6 && range.start <= last.end.saturating_add(1) => {
+				if last.end < range.end {
+					last.end = range.end;
+				}
+			},
+			_ => result.push(Range::from_bits(range.v6, range.start, range.end))
+		}
+	}
+
+	result
+}
+
+// Removes excluded addresses from merged ranges
+// returns what is left and how many addresses were removed
+pub fn subtract(ranges: Vec<Range>, excluded: &[Range]) -> (Vec<Range>, u64) {
+	let mut result: Vec<Range> = vec![];
+	let mut removed = 0;
+
+	for range in ranges.into_iter() {
+		// None once the end of the address space was excluded
+		let mut start = Some(range.start);
+
+		for ex in excluded.iter().filter(|ex| ex.v6 == range.v6 && ex.end >= range.start && ex.start <= range.end) {
+			match start {
+				Some(s) if ex.start > s => result.push(Range::from_bits(range.v6, s, ex.start - 1)),
+				_ => ()
+			};
+			start = match (start, ex.end.checked_add(1)) {
+				(Some(s), Some(next)) => Some(s.max(next)),
+				_ => None
+			};
+		}
+
+		if let Some(start) = start.filter(|s| *s <= range.end) {
+			result.push(Range::from_bits(range.v6, start, range.end));
+		}
+		removed += range.len();
+	}
+
+	removed -= result.iter().map(Range::len).sum::<u64>();
+	(result, removed)
+}
+
+// Parts of sorted and merged ranges that fall inside other
+pub fn intersect(ranges: &[Range], other: &Range) -> Vec<Range> {
+	ranges.iter()
+		.filter(|r| r.v6 == other.v6 && r.end >= other.start && r.start <= other.end)
+		.map(|r| Range::from_bits(r.v6, r.start.max(other.start), r.end.min(other.end)))
+		.collect()
+}
+
+// Whether an address belongs to sorted and merged ranges
+pub fn contains(ranges: &[Range], addr: IpAddr) -> bool {
+	let (v6, value) = bits(addr);
+	let index = ranges.partition_point(|r| (r.v6, r.end) < (v6, value));
+	index < ranges.len() && ranges[index].contains(addr)
+}
+
+/*
+** Targets received one range at a time
+** used when addresses can't be known in advance (stdin, pipes)
+** duplicates are only detected among the last STREAM_DEDUP_WINDOW addresses
+** so that memory usage stays bounded
+*/
+#[derive(Debug)]
+pub struct Stream {
+	source: Receiver<Range>,
+	closed: bool,
+	current: Option<Range>,
+	excluded: Vec<Range>,
+	excluded_count: u64,
+	seen: HashSet<IpAddr>,
+	history: VecDeque<IpAddr>
+}
+
+impl Stream {
+	pub fn new(source: Receiver<Range>, excluded: Vec<Range>) -> Self {
+		Self {
+			source,
+			closed: false,
+			current: None,
+			excluded,
+			excluded_count: 0,
+			seen: HashSet::new(),
+			history: VecDeque::new()
+		}
+	}
+
+	// Number of addresses dropped so far by exclusion lists
+	pub fn excluded(&self) -> u64 {
+		self.excluded_count
+	}
+
+	// Whether the sending side is gone and every target it sent was handed over
+	pub fn is_closed(&self) -> bool {
+		self.closed
+	}
+
+	// Returns false if the address was recently seen
+	fn remember(&mut self, addr: IpAddr) -> bool {
+		if !self.seen.insert(addr) {
+			return false;
+		}
+
+		self.history.push_back(addr);
+		if self.history.len() > STREAM_DEDUP_WINDOW {
+			let oldest = self.history.pop_front().unwrap();
+			self.seen.remove(&oldest);
+		}
+		true
+	}
+}
+
+// Never blocks, None means no target is available for now
+// is_closed tells whether more may come later
+impl Iterator for Stream {
+	type Item = IpAddr;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let addr = match self.current.as_mut().and_then(|range| range.next()) {
+				Some(addr) => addr,
+				None => match self.source.try_recv() {
+					Ok(range) => {
+						self.current = Some(range);
+						continue
+					},
+					Err(TryRecvError::Empty) => return None,
+					Err(TryRecvError::Disconnected) => {
+						self.closed = true;
+						return None;
+					}
+				}
+			};
+
+			if contains(&self.excluded, addr) {
+				self.excluded_count += 1;
+			} else if self.remember(addr) {
+				return Some(addr);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+	use std::sync::mpsc::channel;
+	use super::{Range, Stream, merge, parse, is_spec, subtract, intersect, contains};
+	use crate::STREAM_DEDUP_WINDOW;
+
+	fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+		IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+	}
+
+	#[test]
+	fn cidr_basic_usage() {
+		let range = Range::try_from("10.0.0.0/16").unwrap();
+		assert_eq!(range, Range::new(ip(10, 0, 0, 0), ip(10, 0, 255, 255)));
+		assert_eq!(range.len(), 65536);
+	}
+
+	#[test]
+	fn cidr_host_bits_ignored() {
+		let range = Range::try_from(" 192.168.1.77/30 ").unwrap();
+		assert_eq!(range.collect::<Vec<_>>(), vec![
+			ip(192, 168, 1, 76),
+			ip(192, 168, 1, 77),
+			ip(192, 168, 1, 78),
+			ip(192, 168, 1, 79)
+		]);
+	}
+
+	#[test]
+	fn cidr_edge_prefixes() {
+		assert_eq!(Range::try_from("0.0.0.0/0").unwrap().len(), 1 << 32);
+		assert_eq!(Range::try_from("1.2.3.4/32").unwrap(), Range::from(ip(1, 2, 3, 4)));
+		assert_eq!(Range::try_from("255.255.255.255/31").unwrap().last(), Some(IpAddr::V4(Ipv4Addr::BROADCAST)));
+	}
+
+	#[test]
+	fn cidr_bad_format() {
+		assert!(Range::try_from("10.0.0.0/33").is_err());
+		assert!(Range::try_from("10.0.0.0/").is_err());
+		assert!(Range::try_from("10.0.0/8").is_err());
+		assert!(Range::try_from("localhost/8").is_err());
+	}
+
+	#[test]
+	fn ipv6_blocks() {
+		let range = Range::try_from("2001:db8::1234/126").unwrap();
+		assert!(range.is_ipv6());
+		assert_eq!(range.len(), 4);
+		assert_eq!(range.map(|addr| addr.to_string()).collect::<Vec<_>>(), ["2001:db8::1234", "2001:db8::1235", "2001:db8::1236", "2001:db8::1237"]);
+
+		assert_eq!(parse(" ::1 ").unwrap(), vec![Range::from(Ipv6Addr::LOCALHOST)]);
+		assert_eq!(Range::try_from("2001:db8::/96").unwrap().len(), 1 << 32);
+		assert!(Range::try_from("2001:db8::/64").is_err());
+		assert!(Range::try_from("2001:db8::/129").is_err());
+		assert!(parse("2001:db8::1-5").is_err());
+	}
+
+	#[test]
+	fn octet_basic_usage() {
+		let ranges = parse("192.168.1-3.10-20,30,40").unwrap();
+		assert_eq!(ranges.len(), 9);
+		assert_eq!(ranges[0], Range::new(ip(192, 168, 1, 10), ip(192, 168, 1, 20)));
+		assert_eq!(ranges[1], Range::from(ip(192, 168, 1, 30)));
+		assert_eq!(ranges[8], Range::from(ip(192, 168, 3, 40)));
+		assert_eq!(merge(ranges).iter().map(|r| r.len()).sum::<u64>(), 3 * 13);
+	}
+
+	#[test]
+	fn octet_wildcards() {
+		assert_eq!(parse("10.*.0-255.-").unwrap(), vec![Range::try_from("10.0.0.0/8").unwrap()]);
+		assert_eq!(parse("10.1,3.*.*").unwrap(), vec![Range::try_from("10.1.0.0/16").unwrap(), Range::try_from("10.3.0.0/16").unwrap()]);
+		assert_eq!(parse("10.0.0.250-").unwrap(), vec![Range::new(ip(10, 0, 0, 250), ip(10, 0, 0, 255))]);
+		assert_eq!(parse("*.*.*.*").unwrap(), vec![Range::try_from("0.0.0.0/0").unwrap()]);
+	}
+
+	#[test]
+	fn octet_duplicates() {
+		assert_eq!(parse("10.0.0.1-5,3,4-6").unwrap(), vec![Range::new(ip(10, 0, 0, 1), ip(10, 0, 0, 6))]);
+	}
+
+	#[test]
+	fn octet_bad_format() {
+		let error = |spec: &str| parse(spec).unwrap_err().to_string();
+
+		assert_eq!(error("192.168.1-300.1"), "\"1-300\" is not valid in octet 3 of \"192.168.1-300.1\"");
+		assert_eq!(error("192.168.1.20-10"), "\"20-10\" is not valid in octet 4 of \"192.168.1.20-10\"");
+		assert_eq!(error("192.168.1,,2.1"), "\"\" is not valid in octet 3 of \"192.168.1,,2.1\"");
+		assert_eq!(error("10.0-1.1"), "\"10.0-1.1\" must contain exactly 4 octets");
+		assert!(parse("10.0-1.0.0/16").is_err());
+	}
+
+	#[test]
+	fn octet_too_many_blocks() {
+		assert_eq!(parse("10.*.*.1").unwrap().len(), 1 << 16);
+		assert_eq!(parse("1-255.1-255.1-255.*").unwrap().len(), 255 * 255);
+		assert_eq!(
+			parse("1-255.1-255.1-255.1").unwrap_err().to_string(),
+			"\"1-255.1-255.1-255.1\" expands to 16581375 separate blocks, no more than 262144 are allowed"
+		);
+	}
+
+	#[test]
+	fn spec_or_hostname() {
+		assert!(is_spec("10.0.0.0/8"));
+		assert!(is_spec(" 192.168.1-3.* "));
+		assert!(!is_spec("my-host.internal"));
+		assert!(!is_spec("/tmp/targets.txt"));
+		assert!(is_spec("2001:db8::/120"));
+		assert!(is_spec("::ffff:10.0.0.1"));
+		assert!(!is_spec("cafe"));
+	}
+
+	#[test]
+	fn families_stay_apart() {
+		let merged = merge(vec![
+			Range::try_from("::/126").unwrap(),
+			Range::try_from("0.0.0.4/30").unwrap(),
+			Range::try_from("0.0.0.0/30").unwrap(),
+			Range::try_from("::4/126").unwrap()
+		]);
+		assert_eq!(merged, vec![Range::try_from("0.0.0.0/29").unwrap(), Range::try_from("::/125").unwrap()]);
+
+		let (result, removed) = subtract(merged, &[Range::from(Ipv6Addr::LOCALHOST)]);
+		assert_eq!(removed, 1);
+		assert!(contains(&result, ip(0, 0, 0, 1)));
+		assert!(!contains(&result, IpAddr::V6(Ipv6Addr::LOCALHOST)));
+		assert!(contains(&result, "::2".parse().unwrap()));
+
+		let end = Range::from(Ipv6Addr::from(u128::MAX));
+		assert_eq!(subtract(vec![end], &[end]), (vec![], 1));
+	}
+
+	#[test]
+	fn intersect_ranges() {
+		let ranges = merge(vec![Range::try_from("10.0.0.0/30").unwrap(), Range::try_from("10.0.0.8/30").unwrap()]);
+		let common = intersect(&ranges, &Range::new(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 9)));
+
+		assert_eq!(common.iter().map(Range::to_string).collect::<Vec<_>>(), ["10.0.0.2-10.0.0.3", "10.0.0.8-10.0.0.9"]);
+		assert!(intersect(&ranges, &Range::try_from("::/120").unwrap()).is_empty());
+		assert_eq!(Range::from(Ipv6Addr::LOCALHOST).to_string(), "::1");
+	}
+
+	#[test]
+	fn merge_overlapping_ranges() {
+		let merged = merge(vec![
+			Range::try_from("10.0.0.0/24").unwrap(),
+			Range::try_from("10.0.0.128/25").unwrap(),
+			Range::try_from("10.0.1.0/24").unwrap(),
+			Range::from(ip(127, 0, 0, 1)),
+			Range::from(ip(8, 8, 8, 8))
+		]);
+
+		assert_eq!(merged, vec![
+			Range::from(ip(8, 8, 8, 8)),
+			Range::new(ip(10, 0, 0, 0), ip(10, 0, 1, 255)),
+			Range::from(ip(127, 0, 0, 1))
+		]);
+	}
+
+	#[test]
+	fn subtract_excluded_ranges() {
+		let ranges = merge(vec![Range::try_from("10.0.0.0/24").unwrap(), Range::try_from("10.0.2.0/24").unwrap()]);
+		let excluded = merge(vec![
+			Range::from(ip(10, 0, 0, 0)),
+			Range::try_from("10.0.0.128/25").unwrap(),
+			Range::try_from("10.0.2.0/23").unwrap(),
+			Range::from(ip(192, 168, 0, 1))
+		]);
+
+		let (result, removed) = subtract(ranges, &excluded);
+		assert_eq!(result, vec![Range::new(ip(10, 0, 0, 1), ip(10, 0, 0, 127))]);
+		assert_eq!(removed, 1 + 128 + 256);
+	}
+
+	#[test]
+	fn subtract_edges() {
+		let (result, removed) = subtract(vec![Range::try_from("0.0.0.0/0").unwrap()], &[Range::from(Ipv4Addr::BROADCAST)]);
+		assert_eq!(result, vec![Range::new(Ipv4Addr::UNSPECIFIED, Ipv4Addr::new(255, 255, 255, 254))]);
+		assert_eq!(removed, 1);
+
+		let (result, removed) = subtract(vec![Range::from(Ipv4Addr::LOCALHOST)], &[]);
+		assert_eq!(result, vec![Range::from(Ipv4Addr::LOCALHOST)]);
+		assert_eq!(removed, 0);
+	}
+
+	#[test]
+	fn contains_address() {
+		let ranges = merge(vec![Range::try_from("10.0.0.0/24").unwrap(), Range::from(ip(10, 0, 2, 1))]);
+
+		assert!(contains(&ranges, ip(10, 0, 0, 0)));
+		assert!(contains(&ranges, ip(10, 0, 0, 255)));
+		assert!(contains(&ranges, ip(10, 0, 2, 1)));
+		assert!(!contains(&ranges, ip(10, 0, 1, 0)));
+		assert!(!contains(&ranges, ip(10, 0, 2, 2)));
+		assert!(!contains(&[], ip(10, 0, 2, 2)));
+	}
+
+	#[test]
+	fn stream_dedup_and_exclusions() {
+		let (tx, rx) = channel();
+		let mut stream = Stream::new(rx, vec![Range::from(ip(10, 0, 0, 2))]);
+
+		tx.send(Range::try_from("10.0.0.0/30").unwrap()).unwrap();
+		assert_eq!(Some(ip(10, 0, 0, 0)), stream.next());
+
+		// Sent while the previous range is still being consumed
+		tx.send(Range::try_from("10.0.0.1").unwrap()).unwrap();
+		tx.send(Range::try_from("10.0.0.4").unwrap()).unwrap();
+		drop(tx);
+
+		assert_eq!(stream.collect::<Vec<_>>(), vec![
+			ip(10, 0, 0, 1),
+			ip(10, 0, 0, 3),
+			ip(10, 0, 0, 4)
+		]);
+	}
+
+	#[test]
+	fn stream_waits_for_targets() {
+		let (tx, rx) = channel();
+		let mut stream = Stream::new(rx, vec![]);
+
+		// Nothing read yet, but more may come
+		assert_eq!(stream.next(), None);
+		assert!(!stream.is_closed());
+
+		tx.send(Range::from(ip(10, 0, 0, 1))).unwrap();
+		assert_eq!(stream.next(), Some(ip(10, 0, 0, 1)));
+		drop(tx);
+		assert_eq!(stream.next(), None);
+		assert!(stream.is_closed());
+	}
+
+	#[test]
+	fn stream_dedup_is_bounded() {
+		let (tx, rx) = channel();
+		let mut stream = Stream::new(rx, vec![]);
+		let first = Ipv4Addr::new(10, 0, 0, 0);
+
+		tx.send(Range::new(first, Ipv4Addr::from(u32::from(first) + STREAM_DEDUP_WINDOW as u32))).unwrap();
+		tx.send(Range::from(first)).unwrap();
+		drop(tx);
+
+		assert_eq!(stream.by_ref().count(), STREAM_DEDUP_WINDOW + 2);
+		assert!(stream.seen.len() <= STREAM_DEDUP_WINDOW);
+	}
+}
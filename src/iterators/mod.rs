@@ -1,10 +1,17 @@
-use std::{cmp::PartialEq, fmt::Display, net::Ipv4Addr};
+use std::{cmp::PartialEq, fmt::Display};
 
+pub mod hosts;
+pub mod permutation;
 pub mod ports;
 pub mod scans;
+pub mod services;
+pub mod top_ports;
 
+pub use hosts::Range as HostRange;
 pub use ports::Range as PortRange;
 pub use scans::Scan as ScanType;
+pub use scans::Protocol;
+pub use permutation::{Permutation, Shard};
 
 /*
 ** Structure to hold arguments for scanning
@@ -21,6 +28,10 @@ impl<T: Display> LoopIterator<T> {
 	pub fn new() -> Self {
 		Self { inner: vec![], next: 0 }
 	}
+
+	pub fn as_slice(&self) -> &[T] {
+		&self.inner
+	}
 }
 
 impl<T: Display> From<Vec<T>> for LoopIterator<T> {
@@ -33,9 +44,59 @@ impl<T: Display + PartialEq> PartialEq<Vec<T>> for LoopIterator<T> {
 	fn eq(&self, other: &Vec<T>) -> bool {
 		&self.inner == other
 	}
+}
+
+// Ranges whose values can be computed from their position
+pub trait Span {
+	type Item;
+
+	fn size(&self) -> u64;
+	fn at(&self, offset: u64) -> Self::Item;
+}
+
+/*
+** Random access over the values of a list of ranges
+** only the position of the first value of each range is stored
+*/
+#[derive(Clone, Debug)]
+pub struct Indexed<T: Span> {
+	ranges: Vec<T>,
+	offsets: Vec<u64>,
+	len: u64
+}
+
+impl<T: Span> Indexed<T> {
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
 
-	fn ne(&self, other: &Vec<T>) -> bool {
-		!self.eq(other)
+	// Panics if index is out of bounds
+	pub fn get(&self, index: u64) -> T::Item {
+		let i = self.offsets.partition_point(|offset| *offset <= index) - 1;
+		self.ranges[i].at(index - self.offsets[i])
+	}
+}
+
+impl<T: Span> From<Vec<T>> for Indexed<T> {
+	fn from(ranges: Vec<T>) -> Self {
+		let mut offsets = Vec::with_capacity(ranges.len());
+		let mut len = 0;
+
+		for range in ranges.iter() {
+			offsets.push(len);
+			len += range.size();
+		}
+		Self { ranges, offsets, len }
+	}
+}
+
+impl<T: Span + Display + Clone> From<&LoopIterator<T>> for Indexed<T> {
+	fn from(value: &LoopIterator<T>) -> Self {
+		Self::from(value.inner.clone())
 	}
 }
 
@@ -61,7 +122,6 @@ macro_rules! generic_iter_impl {
 	};
 }
 
-generic_iter_impl!(Ipv4Addr);
 generic_iter_impl!(ScanType);
 
 impl Iterator for LoopIterator<PortRange> {
@@ -89,9 +149,11 @@ impl Iterator for LoopIterator<PortRange> {
 
 #[cfg(test)]
 mod test {
+	use std::net::Ipv4Addr;
+	use crate::iterators::hosts::Range as HostRange;
 	use crate::iterators::ports::Range;
 	use crate::iterators::scans::Scan;
-	use super::LoopIterator;
+	use super::{Indexed, LoopIterator};
 
 	#[test]
 	fn port_iterator_basic() {
@@ -107,6 +169,17 @@ mod test {
 		assert_eq!(None, ports.next());
 	}
 	
+	#[test]
+	fn ranges_equal_while_iterated() {
+		let mut ports = LoopIterator::from(vec![Range::new(1, 3), Range::new(7, 9)]);
+		ports.next();
+		assert!(ports == vec![Range::new(1, 3), Range::new(7, 9)]);
+
+		let mut hosts = HostRange::new(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 3));
+		hosts.next();
+		assert_eq!(hosts, HostRange::new(Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 3)));
+	}
+
 	#[test]
 	fn port_iterator_one_elem() {
 		const VALUE: u16 = u16::MAX;
@@ -144,4 +217,17 @@ mod test {
 		assert_eq!(None, arr.next());
 		assert_eq!(None, arr.next());
 	}
+
+	#[test]
+	fn indexed_ranges() {
+		let ports = Indexed::from(&LoopIterator::from(vec![Range::new(1, 3), Range::new(7, 7), Range::new(10, 11)]));
+		assert_eq!(ports.len(), 6);
+		assert_eq!((0..6).map(|i| ports.get(i)).collect::<Vec<_>>(), vec![1, 2, 3, 7, 10, 11]);
+
+		let hosts = Indexed::from(vec![HostRange::try_from("10.0.0.0/24").unwrap(), HostRange::from(Ipv4Addr::new(10, 0, 2, 1))]);
+		assert_eq!(hosts.len(), 257);
+		assert_eq!(hosts.get(255), Ipv4Addr::new(10, 0, 0, 255));
+		assert_eq!(hosts.get(256), Ipv4Addr::new(10, 0, 2, 1));
+		assert!(Indexed::<Range>::from(vec![]).is_empty());
+	}
 }
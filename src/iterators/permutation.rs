@@ -0,0 +1,145 @@
+/*
+** Keyed bijection of [0, size)
+** a balanced Feistel network permutes the smallest even power of two
+** that holds size, values that fall outside are fed back into it
+** until they land in range (cycle walking)
+** nothing but the round keys is stored, whatever the size
+*/
+#[derive(Clone, Debug)]
+pub struct Permutation {
+	size: u64,
+	half: u32,
+	keys: [u64; ROUNDS]
+}
+
+const ROUNDS: usize = 4;
+
+// SplitMix64 finalizer, good enough to mix round inputs
+fn mix(mut x: u64) -> u64 {
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+	x ^ (x >> 31)
+}
+
+impl Permutation {
+	pub fn new(size: u64, seed: u64) -> Self {
+		let bits = 64 - size.saturating_sub(1).leading_zeros();
+		let mut keys = [0; ROUNDS];
+		let mut state = seed;
+
+		for key in keys.iter_mut() {
+			state = state.wrapping_add(0x9e3779b97f4a7c15);
+			*key = mix(state);
+		}
+		Self { size, half: bits.div_ceil(2).max(1), keys }
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size
+	}
+
+	// Position of index once shuffled, index must be lower than size
+	pub fn get(&self, index: u64) -> u64 {
+		let mut value = self.encrypt(index);
+
+		// A cycle that starts in range always comes back in range
+		// and at most 3/4 of the domain is out of it
+		while value >= self.size {
+			value = self.encrypt(value);
+		}
+		value
+	}
+
+	fn encrypt(&self, value: u64) -> u64 {
+		let mask = (1u64 << self.half) - 1;
+		let (mut left, mut right) = ((value >> self.half) & mask, value & mask);
+
+		for key in self.keys.iter() {
+			(left, right) = (right, left ^ (mix(right ^ key) & mask));
+		}
+		(left << self.half) | right
+	}
+}
+
+/*
+** One of count slices of the probe sequence
+** shard n out of m takes every m-th probe starting with the n-th one
+** so slices never overlap and always add up to the whole sequence
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+	pub index: u64,
+	pub count: u64
+}
+
+impl Default for Shard {
+	fn default() -> Self {
+		Self { index: 0, count: 1 }
+	}
+}
+
+impl std::fmt::Display for Shard {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}/{}", self.index + 1, self.count)
+	}
+}
+
+// "2/3" is the second shard out of three
+pub fn parse_shard(str: &str) -> Result<Shard, String> {
+	let error = || format!("\"{str}\" is not a valid shard, expected N/M with 1 <= N <= M");
+	let (index, count) = str.trim().split_once('/').ok_or_else(error)?;
+	let (index, count) = match (index.trim().parse::<u64>(), count.trim().parse::<u64>()) {
+		(Ok(index), Ok(count)) => (index, count),
+		_ => return Err(error())
+	};
+
+	if index == 0 || index > count {
+		return Err(error());
+	}
+	Ok(Shard { index: index - 1, count })
+}
+
+#[cfg(test)]
+mod test {
+	use super::{parse_shard, Permutation, Shard};
+
+	#[test]
+	fn permutation_is_bijective() {
+		for size in [1, 2, 3, 17, 255, 256, 1000, 4097] {
+			let permutation = Permutation::new(size, 42);
+			let mut values: Vec<u64> = (0..size).map(|i| permutation.get(i)).collect();
+			values.sort();
+			assert_eq!(values, (0..size).collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn permutation_is_keyed() {
+		let a: Vec<u64> = (0..1000).map(|i| Permutation::new(1000, 1).get(i)).collect();
+		let b: Vec<u64> = (0..1000).map(|i| Permutation::new(1000, 1).get(i)).collect();
+		let c: Vec<u64> = (0..1000).map(|i| Permutation::new(1000, 2).get(i)).collect();
+
+		assert_eq!(a, b);
+		assert_ne!(a, c);
+		assert_ne!(a, (0..1000).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn permutation_huge_domain() {
+		let permutation = Permutation::new(u64::MAX, 7);
+		assert!(permutation.get(u64::MAX - 1) < u64::MAX);
+		assert!(Permutation::new(1 << 40, 7).get(12345) < 1 << 40);
+	}
+
+	#[test]
+	fn shard_parsing() {
+		assert_eq!(parse_shard("1/1"), Ok(Shard::default()));
+		assert_eq!(parse_shard(" 3/4 "), Ok(Shard { index: 2, count: 4 }));
+		assert_eq!(parse_shard("3/4").unwrap().to_string(), "3/4");
+		assert!(parse_shard("0/4").is_err());
+		assert!(parse_shard("5/4").is_err());
+		assert!(parse_shard("1/0").is_err());
+		assert!(parse_shard("2").is_err());
+		assert!(parse_shard("a/b").is_err());
+	}
+}
@@ -1,23 +1,152 @@
-use super::LoopIterator;
+use super::{services, LoopIterator, Protocol};
 use clap::error::ErrorKind;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Copy)]
+// Ranges without a protocol are probed by every scan
+#[derive(Clone, Debug, Copy)]
 pub struct Range {
+	start: u16,
 	current: Option<u16>,
-	end: u16
+	end: u16,
+	protocol: Option<Protocol>
+}
+
+// Ranges are compared by their bounds, however far they were iterated
+impl PartialEq for Range {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other).is_eq()
+	}
+}
+
+impl Eq for Range {}
+
+impl PartialOrd for Range {
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Range {
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+		(self.start, self.end, self.protocol).cmp(&(other.start, other.end, other.protocol))
+	}
 }
 
 impl Range {
 	pub fn new(start: u16, end: u16) -> Self {
-		Range { current: Some(start), end }
+		Range { start, current: Some(start), end, protocol: None }
+	}
+
+	pub fn with_protocol(self, protocol: Option<Protocol>) -> Self {
+		Range { protocol, ..self }
+	}
+
+	pub fn protocol(&self) -> Option<Protocol> {
+		self.protocol
+	}
+
+	// Whether scans of this protocol probe the range
+	pub fn applies_to(&self, protocol: Protocol) -> bool {
+		self.protocol.is_none_or(|p| p == protocol)
+	}
+
+	// Part of the range up to max, if any
+	pub fn below(&self, max: u16) -> Option<Self> {
+		(self.start <= max).then(|| Range::new(self.start, self.end.min(max)).with_protocol(self.protocol))
+	}
+
+	pub(crate) fn len(&self) -> u64 {
+		(self.end - self.start) as u64 + 1
+	}
+}
+
+// Sorted by protocol, overlapping ranges of the same protocol are merged
+pub fn merge(mut ranges: Vec<Range>) -> Vec<Range> {
+	let mut merged: Vec<Range> = vec![];
+
+	ranges.sort_by_key(|range| (range.protocol, range.start, range.end));
+	for range in ranges.into_iter() {
+		match merged.last_mut() {
+			Some(last) if last.protocol == range.protocol && last.end >= range.start => last.end = last.end.max(range.end),
+			_ => merged.push(Range::new(range.start, range.end).with_protocol(range.protocol))
+		}
+	}
+
+	merged
+}
+
+// Removes excluded ports from the ranges they apply to
+// ranges without a protocol are only cut by exclusions without one either
+pub fn subtract(ranges: Vec<Range>, excluded: &[Range]) -> Vec<Range> {
+	let mut result: Vec<Range> = vec![];
+
+	for range in ranges.into_iter() {
+		let mut start = Some(range.start);
+		let cuts = excluded.iter().filter(|e| e.protocol.is_none() || e.protocol == range.protocol);
+
+		for cut in merge(cuts.map(|e| e.with_protocol(None)).collect()).iter() {
+			match start {
+				Some(s) if cut.start <= range.end && cut.end >= s => {
+					if cut.start > s {
+						result.push(Range::new(s, cut.start - 1).with_protocol(range.protocol));
+					}
+					start = cut.end.checked_add(1);
+				},
+				_ => continue
+			}
+		}
+
+		if let Some(s) = start.filter(|s| *s <= range.end) {
+			result.push(Range::new(s, range.end).with_protocol(range.protocol));
+		}
+	}
+
+	result
+}
+
+// Removes excluded ports from a merged list
+// ranges without a protocol are split by protocol where a qualified exclusion cuts them
+pub fn exclude(ranges: &[Range], excluded: &[Range]) -> Vec<Range> {
+	let qualified: Vec<&Range> = excluded.iter().filter(|e| e.protocol.is_some()).collect();
+	let mut split: Vec<Range> = vec![];
+
+	for range in ranges.iter() {
+		if range.protocol.is_some() || !qualified.iter().any(|e| e.start <= range.end && e.end >= range.start) {
+			split.push(*range);
+			continue ;
+		}
+		split.extend([Protocol::Tcp, Protocol::Udp, Protocol::Sctp, Protocol::Ip].map(|protocol| range.with_protocol(Some(protocol))));
+	}
+
+	merge(subtract(split, excluded))
+}
+
+// Whether one of the ranges holds this port of this protocol
+pub fn contains(ranges: &[Range], protocol: Protocol, port: u16) -> bool {
+	ranges.iter().any(|range| range.applies_to(protocol) && (range.start..=range.end).contains(&port))
+}
+
+impl super::Span for Range {
+	type Item = u16;
+
+	fn size(&self) -> u64 {
+		self.len()
+	}
+
+	fn at(&self, offset: u64) -> u16 {
+		self.start + offset as u16
 	}
 }
 
-// Panics if the iterator is already consumed
-// we don't really care because this impl is just for clap::Parser
 impl std::fmt::Display for Range {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{}-{}", self.current.unwrap(), self.end)
+		match self.protocol {
+			Some(Protocol::Tcp) => write!(f, "T:")?,
+			Some(Protocol::Udp) => write!(f, "U:")?,
+			Some(Protocol::Sctp) => write!(f, "S:")?,
+			Some(Protocol::Ip) => write!(f, "P:")?,
+			None => ()
+		}
+		write!(f, "{}-{}", self.start, self.end)
 	}
 }
 
@@ -47,9 +176,7 @@ impl Default for LoopIterator<Range> {
 
 impl std::fmt::Display for LoopIterator<Range> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		self.inner.iter().fold(Ok(()), |result, range| {
-			result.and_then(|_| write!(f, "{},", range))
-		})
+		self.inner.iter().try_for_each(|range| write!(f, "{},", range))
 	}
 }
 
@@ -63,51 +190,28 @@ impl Parser {
 	}
 
 	#[allow(non_snake_case)]
-	fn RangeTooBig(cmd: &clap::Command) -> clap::Error {
-		clap::Error::raw(ErrorKind::InvalidValue, format!("Cannot scan more than 1024 ports\n")).with_cmd(cmd)
+	fn UnknownService(value: &str, cmd: &clap::Command) -> clap::Error {
+		clap::Error::raw(ErrorKind::ValueValidation, format!("\"{}\" is not a known service name\n", value.trim())).with_cmd(cmd)
 	}
 
-	fn validate(mut array: Vec<Range>, cmd: &clap::Command) -> Result<Vec<Range>, clap::Error> {
-		let mut last: Option<Range> = None;
-		let mut result: Vec<Range> = vec![];
-
-		array.sort();
-		for pair in array.windows(2) { // iterate over pairs of consecutive ranges
-			if let Some(ref mut tmp) = last {
-				if tmp.end >= pair[1].current.unwrap() { 
-					if tmp.end < pair[1].end { // temporary range still overlaps with next one
-						tmp.end = pair[1].end; // therefore extend it
-					}
-				} else { // no more overlaps
-					result.push(*tmp); // save temp range
-					last = None;
-				}			
-			} else {
-				if pair[0].end >= pair[1].current.unwrap() { // overlapping ranges
-					if pair[0].end >= pair[1].end { // create a temporary range that contains both
-						last = Some(Range::new(pair[0].current.unwrap(), pair[0].end));
-					} else {
-						last = Some(Range::new(pair[0].current.unwrap(), pair[1].end));
-					}
-				} else { // no overlaps, do nothing
-					result.push(pair[0]);
-				}
-			}
-		}
-
-		if let Some(last) = last {
-			result.push(last);
-		} else {
-			result.push(*array.last().unwrap());
-		}
-
-		if result.iter().fold(0, |acc, r| acc + (r.end - r.current.unwrap()) + 1) > 1024 {
-			return Err(Self::RangeTooBig(cmd)); // cannot scan more than 1024 ports at once
-		}
+	// Ports given without a protocol already cover qualified ones
+	fn validate(array: Vec<Range>) -> Vec<Range> {
+		let (any, qualified): (Vec<Range>, Vec<Range>) = merge(array).into_iter().partition(|range| range.protocol.is_none());
+		let qualified = subtract(qualified, &any);
+		[any, qualified].concat()
+	}
 
-		array = std::mem::take(&mut result);
-		
-		Ok(array)
+	// "T:", "U:", "S:" or "P:" in front of a range applies to it and the ones after
+	fn qualifier(range: &str) -> (Option<Option<Protocol>>, &str) {
+		let trimmed = range.trim_start();
+		let protocol = match trimmed.get(..2).map(str::to_uppercase).as_deref() {
+			Some("T:") => Protocol::Tcp,
+			Some("U:") => Protocol::Udp,
+			Some("S:") => Protocol::Sctp,
+			Some("P:") => Protocol::Ip,
+			_ => return (None, range)
+		};
+		(Some(Some(protocol)), &trimmed[2..])
 	}
 }
 
@@ -124,14 +228,39 @@ impl clap::builder::TypedValueParser for Parser {
 		let str = inner.parse_ref(cmd, arg, raw_value)?;
 
 		let mut ports = Vec::<Range>::new();
+		let mut protocol: Option<Protocol> = None;
 		for range in str.split(',') { // ',' separates ranges
-			if range.is_empty() {
+			let range = match Self::qualifier(range) {
+				(Some(qualifier), range) => {
+					protocol = qualifier;
+					range
+				},
+				(None, range) => range
+			};
+			if range.trim().is_empty() {
+				continue ;
+			}
+
+			// Service names may contain '-' themselves, so they are never range bounds
+			if range.trim().starts_with(|c: char| c.is_ascii_alphabetic()) {
+				let port = services::table().port(range, protocol).ok_or_else(|| Self::UnknownService(range, cmd))?;
+				ports.push(Range::new(port, port).with_protocol(protocol));
 				continue ;
 			}
-			
+
 			let values: Vec<&str> = range.splitn(2, '-').collect(); // '-' separates range bounds
 			let mut r: [u16; 2] = [0, 0];
 
+			// Missing bounds stand for the first and last ports, "-" alone means all of them
+			if values.len() == 2 && (values[0].trim().is_empty() || values[1].trim().is_empty()) {
+				let bound = |v: &str, default: u16| match v.trim() {
+					"" => Ok(default),
+					v => v.parse::<u16>().ok().filter(|v| *v != 0).ok_or_else(|| Self::InvalidValue(v, cmd))
+				};
+				ports.push(Range::new(bound(values[0], 1)?, bound(values[1], u16::MAX)?).with_protocol(protocol));
+				continue ;
+			}
+
 			for i in 0..values.len() {
 				if let Ok(v) = values[i].trim().parse::<u16>() {
 					if v != 0 {
@@ -146,13 +275,13 @@ impl clap::builder::TypedValueParser for Parser {
 			}
 
 			if r[0] < r[1] {
-				ports.push(Range::new(r[0], r[1]));
+				ports.push(Range::new(r[0], r[1]).with_protocol(protocol));
 			} else {
-				ports.push(Range::new(r[1], r[0]));
+				ports.push(Range::new(r[1], r[0]).with_protocol(protocol));
 			}
 		}
 
-		ports = Self::validate(ports, cmd)?;
+		ports = Self::validate(ports);
 		Ok(LoopIterator::from(ports))
 	}
 }
@@ -4,7 +4,90 @@ use num_enum::IntoPrimitive;
 
 #[derive (IntoPrimitive, Clone, Copy, Debug, PartialEq, Ord, PartialOrd, Eq)]
 #[repr(u8)]
-pub enum Scan { SYN, NULL, ACK, FIN, XMAS, UDP }
+pub enum Scan { SYN, NULL, ACK, FIN, XMAS, UDP, CONNECT, WINDOW, MAIMON, CUSTOM, INIT, COOKIE, PROTO, IDLE, BOUNCE }
+
+// Transport protocol a scan type probes, IP for scans of the protocols themselves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Protocol { Tcp, Udp, Sctp, Ip }
+
+impl Scan {
+	pub fn protocol(self) -> Protocol {
+		match self {
+			Scan::UDP	=> Protocol::Udp,
+			Scan::INIT | Scan::COOKIE	=> Protocol::Sctp,
+			Scan::PROTO	=> Protocol::Ip,
+			_			=> Protocol::Tcp
+		}
+	}
+}
+
+// What no answer to a CUSTOM probe means
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Silence { #[default] OpenFiltered, Filtered }
+
+// What a RST answer to a CUSTOM probe means
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reset { #[default] Closed, Unfiltered }
+
+const FLAG_NAMES: [(&str, u16); 9] = [
+	("FIN", 0x001), ("SYN", 0x002), ("RST", 0x004), ("PSH", 0x008), ("ACK", 0x010),
+	("URG", 0x020), ("ECE", 0x040), ("CWR", 0x080), ("NS", 0x100)
+];
+
+// Flag names run together or separated, like URGPSHFIN or SYN,ECE, or a number like 0x29
+pub fn parse_flags(str: &str) -> Result<u16, String> {
+	let error = || format!("\"{str}\" is not a valid set of TCP flags, expected names like URGPSHFIN or a number");
+	let spec = str.trim().to_uppercase();
+	let number = match spec.strip_prefix("0X") {
+		Some(hex) => u16::from_str_radix(hex, 16),
+		None => spec.parse::<u16>()
+	};
+	if let Ok(flags) = number {
+		return (flags <= 0x1ff).then_some(flags).ok_or_else(error);
+	}
+
+	let mut rest: &str = &spec;
+	let mut flags = 0;
+	while let Some(c) = rest.chars().next() {
+		if matches!(c, ',' | '|' | '+' | ' ') {
+			rest = &rest[1..];
+			continue ;
+		}
+		let (name, flag) = FLAG_NAMES.iter().find(|(name, _)| rest.starts_with(name)).ok_or_else(error)?;
+		flags |= flag;
+		rest = &rest[name.len()..];
+	}
+
+	match spec.is_empty() {
+		true => Err(error()),
+		false => Ok(flags)
+	}
+}
+
+impl TryFrom<&str> for Protocol {
+	type Error = ();
+
+	fn try_from(str: &str) -> Result<Self, <Self as TryFrom<&str>>::Error> {
+		match str.to_lowercase().as_str() {
+			"tcp"	=> Ok(Self::Tcp),
+			"udp"	=> Ok(Self::Udp),
+			"sctp"	=> Ok(Self::Sctp),
+			"ip"	=> Ok(Self::Ip),
+			_		=> Err(())
+		}
+	}
+}
+
+impl std::fmt::Display for Protocol {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", match self {
+			Protocol::Tcp => "tcp",
+			Protocol::Udp => "udp",
+			Protocol::Sctp => "sctp",
+			Protocol::Ip => "ip"
+		})
+	}
+}
 
 impl From<Scan> for String {
 	fn from(scan: Scan) -> Self {
@@ -14,7 +97,16 @@ impl From<Scan> for String {
 			Scan::ACK	=> "ACK",
 			Scan::FIN	=> "FIN",
 			Scan::XMAS	=> "XMAS",
-			Scan::UDP	=> "UDP"
+			Scan::UDP	=> "UDP",
+			Scan::CONNECT	=> "CONNECT",
+			Scan::WINDOW	=> "WINDOW",
+			Scan::MAIMON	=> "MAIMON",
+			Scan::CUSTOM	=> "CUSTOM",
+			Scan::INIT	=> "INIT",
+			Scan::COOKIE	=> "COOKIE",
+			Scan::PROTO	=> "PROTO",
+			Scan::IDLE	=> "IDLE",
+			Scan::BOUNCE	=> "BOUNCE"
 		})
 	}	
 }
@@ -29,6 +121,9 @@ impl TryFrom<Scan> for u16 {
 			Scan::ACK	=> Ok(0b01_0000),
 			Scan::FIN	=> Ok(0b00_0001),
 			Scan::XMAS	=> Ok(0b10_1001),
+			Scan::WINDOW	=> Ok(0b01_0000),
+			Scan::MAIMON	=> Ok(0b01_0001),
+			Scan::IDLE	=> Ok(0b00_0010), // sent in the zombie's name
 			_			=> Err(())
 		}
 	}
@@ -45,6 +140,15 @@ impl TryFrom<&str> for Scan {
 			"FIN"	=> Ok(Self::FIN),
 			"XMAS"	=> Ok(Self::XMAS),
 			"UDP"	=> Ok(Self::UDP),
+			"CONNECT"	=> Ok(Self::CONNECT),
+			"WINDOW"	=> Ok(Self::WINDOW),
+			"MAIMON"	=> Ok(Self::MAIMON),
+			"CUSTOM"	=> Ok(Self::CUSTOM),
+			"INIT"	=> Ok(Self::INIT),
+			"COOKIE"	=> Ok(Self::COOKIE),
+			"PROTO"	=> Ok(Self::PROTO),
+			"IDLE"	=> Ok(Self::IDLE),
+			"BOUNCE"	=> Ok(Self::BOUNCE),
 			_		=> Err(())
 		}
 	}
@@ -64,11 +168,11 @@ impl Default for LoopIterator<Scan> {
 
 impl std::fmt::Display for LoopIterator<Scan> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		self.inner.iter().fold(Ok(()), |result, scan| {
-			result.and_then(|_| write!(f, "{}{}", scan, match scan != self.inner.last().unwrap() {
+		self.inner.iter().try_for_each(|scan| {
+			write!(f, "{}{}", scan, match scan != self.inner.last().unwrap() {
 				true => ",",
 				false => ""
-			}))
+			})
 		})
 	}
 }
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::Protocol;
+use crate::SERVICES_PATH;
+
+// Used for services /etc/services does not know about, or when it is missing
+const FALLBACK: &[(&str, u16, Protocol)] = &[
+	("ftp-data", 20, Protocol::Tcp),
+	("ftp", 21, Protocol::Tcp),
+	("ssh", 22, Protocol::Tcp),
+	("telnet", 23, Protocol::Tcp),
+	("smtp", 25, Protocol::Tcp),
+	("domain", 53, Protocol::Tcp),
+	("domain", 53, Protocol::Udp),
+	("bootps", 67, Protocol::Udp),
+	("bootpc", 68, Protocol::Udp),
+	("tftp", 69, Protocol::Udp),
+	("http", 80, Protocol::Tcp),
+	("kerberos", 88, Protocol::Tcp),
+	("kerberos", 88, Protocol::Udp),
+	("pop3", 110, Protocol::Tcp),
+	("sunrpc", 111, Protocol::Tcp),
+	("sunrpc", 111, Protocol::Udp),
+	("ntp", 123, Protocol::Udp),
+	("epmap", 135, Protocol::Tcp),
+	("netbios-ns", 137, Protocol::Udp),
+	("netbios-dgm", 138, Protocol::Udp),
+	("netbios-ssn", 139, Protocol::Tcp),
+	("imap", 143, Protocol::Tcp),
+	("snmp", 161, Protocol::Udp),
+	("snmp-trap", 162, Protocol::Udp),
+	("ldap", 389, Protocol::Tcp),
+	("https", 443, Protocol::Tcp),
+	("https", 443, Protocol::Udp),
+	("microsoft-ds", 445, Protocol::Tcp),
+	("isakmp", 500, Protocol::Udp),
+	("syslog", 514, Protocol::Udp),
+	("submission", 587, Protocol::Tcp),
+	("ipp", 631, Protocol::Tcp),
+	("ipp", 631, Protocol::Udp),
+	("ldaps", 636, Protocol::Tcp),
+	("imaps", 993, Protocol::Tcp),
+	("pop3s", 995, Protocol::Tcp),
+	("openvpn", 1194, Protocol::Udp),
+	("ms-sql-s", 1433, Protocol::Tcp),
+	("ms-sql-m", 1434, Protocol::Udp),
+	("mqtt", 1883, Protocol::Tcp),
+	("ssdp", 1900, Protocol::Udp),
+	("nfs", 2049, Protocol::Tcp),
+	("nfs", 2049, Protocol::Udp),
+	("mysql", 3306, Protocol::Tcp),
+	("ms-wbt-server", 3389, Protocol::Tcp),
+	("ipsec-nat-t", 4500, Protocol::Udp),
+	("mdns", 5353, Protocol::Udp),
+	("postgresql", 5432, Protocol::Tcp),
+	("amqp", 5672, Protocol::Tcp),
+	("vnc", 5900, Protocol::Tcp),
+	("x11", 6000, Protocol::Tcp),
+	("redis", 6379, Protocol::Tcp),
+	("http-alt", 8080, Protocol::Tcp),
+	("https-alt", 8443, Protocol::Tcp),
+	("jetdirect", 9100, Protocol::Tcp),
+	("memcached", 11211, Protocol::Tcp),
+	("mongodb", 27017, Protocol::Tcp),
+	// IP protocol numbers, as in /etc/protocols
+	("icmp", 1, Protocol::Ip),
+	("igmp", 2, Protocol::Ip),
+	("ipencap", 4, Protocol::Ip),
+	("tcp", 6, Protocol::Ip),
+	("udp", 17, Protocol::Ip),
+	("ipv6", 41, Protocol::Ip),
+	("gre", 47, Protocol::Ip),
+	("esp", 50, Protocol::Ip),
+	("ah", 51, Protocol::Ip),
+	("ipv6-icmp", 58, Protocol::Ip),
+	("ospf", 89, Protocol::Ip),
+	("pim", 103, Protocol::Ip),
+	("vrrp", 112, Protocol::Ip),
+	("l2tp", 115, Protocol::Ip),
+	("sctp", 132, Protocol::Ip)
+];
+
+/*
+** Service names by port and ports by service name, for each protocol
+** aliases resolve to their port but ports are only named after
+** the first service listed for them
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+	ports: HashMap<(String, Protocol), u16>,
+	names: HashMap<(u16, Protocol), String>
+}
+
+impl Table {
+	// Lines look like "name port/protocol [aliases...] [# comment]"
+	// lines that don't are ignored, like the C library does
+	pub fn parse(content: &str) -> Self {
+		let mut table = Self::default();
+
+		for line in content.lines() {
+			let line = line.split('#').next().unwrap_or_default();
+			let mut fields = line.split_whitespace();
+			let (name, entry) = match (fields.next(), fields.next()) {
+				(Some(name), Some(entry)) => (name, entry),
+				_ => continue
+			};
+			let (port, protocol) = match entry.split_once('/') {
+				Some((port, protocol)) => (port.parse::<u16>(), Protocol::try_from(protocol)),
+				None => continue
+			};
+
+			if let (Ok(port), Ok(protocol)) = (port, protocol) {
+				table.insert(name, port, protocol);
+				fields.for_each(|alias| table.insert(alias, port, protocol));
+			}
+		}
+
+		table
+	}
+
+	// Entries already there win
+	fn insert(&mut self, name: &str, port: u16, protocol: Protocol) {
+		self.ports.entry((name.to_lowercase(), protocol)).or_insert(port);
+		self.names.entry((port, protocol)).or_insert_with(|| name.to_string());
+	}
+
+	// Without a protocol, TCP services are looked up before UDP ones
+	pub fn port(&self, name: &str, protocol: Option<Protocol>) -> Option<u16> {
+		let name = name.trim().to_lowercase();
+		let protocols = match protocol {
+			Some(protocol) => vec![protocol],
+			None => vec![Protocol::Tcp, Protocol::Udp]
+		};
+
+		protocols.into_iter().find_map(|protocol| self.ports.get(&(name.clone(), protocol)).copied())
+	}
+
+	pub fn name(&self, port: u16, protocol: Protocol) -> Option<&str> {
+		self.names.get(&(port, protocol)).map(String::as_str)
+	}
+}
+
+static TABLE: OnceLock<Table> = OnceLock::new();
+
+// Read once, on first use
+pub fn table() -> &'static Table {
+	TABLE.get_or_init(|| {
+		let mut table = Table::parse(&std::fs::read_to_string(SERVICES_PATH).unwrap_or_default());
+		for (name, port, protocol) in FALLBACK.iter() {
+			table.insert(name, *port, *protocol);
+		}
+		table
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::{table, Table};
+	use crate::iterators::Protocol;
+
+	#[test]
+	fn parse_services() {
+		let table = Table::parse("\
+			# comment\n\
+			ssh\t\t22/tcp\t\t\t# SSH\n\
+			http\t\t80/tcp\t\twww www-http\n\
+			snmp\t\t161/udp\n\
+			broken\t\t70000/tcp\n\
+			nothing\n\
+			sctp-only\t9/sctp\n\
+			alt-http\t80/tcp\n");
+
+		assert_eq!(table.port("ssh", None), Some(22));
+		assert_eq!(table.port("WWW", None), Some(80));
+		assert_eq!(table.port("snmp", None), Some(161));
+		assert_eq!(table.port("snmp", Some(Protocol::Tcp)), None);
+		assert_eq!(table.port("broken", None), None);
+		assert_eq!(table.port("sctp-only", None), None);
+		assert_eq!(table.name(80, Protocol::Tcp), Some("http"));
+		assert_eq!(table.name(80, Protocol::Udp), None);
+	}
+
+	#[test]
+	fn fallback_services() {
+		assert_eq!(table().port("postgresql", None), Some(5432));
+		assert_eq!(table().port("ntp", None), Some(123));
+		assert_eq!(table().name(53, Protocol::Udp), Some("domain"));
+		assert_eq!(table().name(22, Protocol::Tcp), Some("ssh"));
+	}
+}
@@ -0,0 +1,181 @@
+use super::{ports, LoopIterator, PortRange, Protocol};
+
+// Rankings come from Nmap's nmap-services (https://nmap.org/book/nmap-services.html)
+// which tells how often each port was found open on the Internet
+
+// The 100 most frequent TCP ports, with their frequencies
+const TCP: &[(u16, f64)] = &[
+	(80, 0.484143), (23, 0.221265), (443, 0.208669), (21, 0.197667), (22, 0.182286),
+	(25, 0.131314), (3389, 0.083904), (110, 0.077142), (445, 0.056944), (139, 0.050809),
+	(143, 0.050420), (53, 0.048463), (135, 0.047798), (3306, 0.045390), (8080, 0.042052),
+	(1723, 0.039738), (111, 0.030034), (995, 0.029393), (993, 0.027698), (5900, 0.023741),
+	(1025, 0.019937), (587, 0.019721), (8888, 0.016402), (199, 0.016096), (1720, 0.014566),
+	(465, 0.013848), (548, 0.012372), (113, 0.011612), (81, 0.010657), (6001, 0.010586),
+	(10000, 0.010565), (514, 0.009709), (5060, 0.009653), (179, 0.008990), (1026, 0.008807),
+	(2000, 0.008657), (8443, 0.008341), (8000, 0.008178), (32768, 0.007965), (554, 0.007843),
+	(26, 0.007466), (1433, 0.007366), (49152, 0.007248), (2001, 0.007097), (515, 0.006969),
+	(8008, 0.006942), (49154, 0.006900), (1027, 0.006697), (5666, 0.006590), (646, 0.006497),
+	(5000, 0.006438), (5631, 0.006248), (631, 0.006008), (49153, 0.005856), (8081, 0.005760),
+	(2049, 0.005635), (88, 0.005587), (79, 0.005557), (5800, 0.005353), (106, 0.005253),
+	(2121, 0.005174), (1110, 0.005126), (49155, 0.005078), (6000, 0.005000), (513, 0.004958),
+	(990, 0.004933), (5357, 0.004829), (427, 0.004778), (49156, 0.004753), (543, 0.004693),
+	(544, 0.004692), (5101, 0.004632), (144, 0.004626), (7, 0.004608), (389, 0.004574),
+	(8009, 0.004470), (3128, 0.004463), (444, 0.004453), (9999, 0.004396), (5009, 0.004344),
+	(7070, 0.004298), (5190, 0.004286), (3000, 0.004259), (5432, 0.004237), (1900, 0.004199),
+	(3986, 0.004131), (13, 0.004071), (1029, 0.004049), (9, 0.004019), (5051, 0.003990),
+	(6646, 0.003972), (49157, 0.003952), (1028, 0.003931), (873, 0.003908), (1755, 0.003888),
+	(2717, 0.003867), (4899, 0.003850), (9100, 0.003829), (119, 0.003809), (37, 0.003789)
+];
+
+// The other 900 of the 1000 TCP ports Nmap scans by default, all less frequent than the ones above
+// the first 144 in order of frequency, then by port number
+const TCP_TAIL: &[u16] = &[
+	1000, 3001, 5001, 82, 10010, 1030, 9090, 2107, 1024, 2103, 6004, 1801, 5050, 19, 8031, 1041, 255, 1049, 1048, 2967,
+	1053, 3703, 1056, 1065, 1064, 1054, 17, 808, 3689, 1031, 1044, 1071, 5901, 100, 9102, 8010, 2869, 1039, 5120, 4001,
+	9000, 2105, 636, 1038, 2601, 1, 7000, 1066, 1069, 625, 311, 280, 254, 4000, 1761, 5003, 2002, 2005, 1998, 1032,
+	1050, 6112, 3690, 1521, 2161, 6002, 1080, 2401, 4045, 902, 7937, 787, 1058, 2383, 32771, 1033, 1040, 1059, 50000, 5555,
+	10001, 1494, 593, 2301, 3, 3268, 7938, 1234, 1022, 1074, 8002, 1036, 1035, 9001, 1037, 464, 497, 1935, 6666, 26214,
+	1086, 8082, 1081, 1046, 1021, 8001, 1068, 1051, 32769, 1045, 2048, 10002, 6003, 1043, 2003, 4444, 1042, 2004, 1099, 1067,
+	1062, 1070, 32772, 1052, 1060, 1077, 1072, 1076, 1063, 1078, 8085, 15000, 1047, 1079, 1057, 1055, 1082, 5002, 9010, 32770,
+	8084, 9003, 1061, 8011, 4, 6, 20, 24, 30, 32, 33, 42, 43, 49, 70, 83, 84, 85, 89, 90,
+	99, 109, 125, 146, 161, 163, 211, 212, 222, 256, 259, 264, 301, 306, 340, 366, 406, 407, 416, 417,
+	425, 458, 481, 500, 512, 524, 541, 545, 555, 563, 616, 617, 648, 666, 667, 668, 683, 687, 691, 700,
+	705, 711, 714, 720, 722, 726, 749, 765, 777, 783, 800, 801, 843, 880, 888, 898, 900, 901, 903, 911,
+	912, 981, 987, 992, 999, 1001, 1002, 1007, 1009, 1010, 1011, 1023, 1034, 1073, 1075, 1083, 1084, 1085, 1087, 1088,
+	1089, 1090, 1091, 1092, 1093, 1094, 1095, 1096, 1097, 1098, 1100, 1102, 1104, 1105, 1106, 1107, 1108, 1111, 1112, 1113,
+	1114, 1117, 1119, 1121, 1122, 1123, 1124, 1126, 1130, 1131, 1132, 1137, 1138, 1141, 1145, 1147, 1148, 1149, 1151, 1152,
+	1154, 1163, 1164, 1165, 1166, 1169, 1174, 1175, 1183, 1185, 1186, 1187, 1192, 1198, 1199, 1201, 1213, 1216, 1217, 1218,
+	1233, 1236, 1244, 1247, 1248, 1259, 1271, 1272, 1277, 1287, 1296, 1300, 1301, 1309, 1310, 1311, 1322, 1328, 1334, 1352,
+	1417, 1434, 1443, 1455, 1461, 1500, 1501, 1503, 1524, 1533, 1556, 1580, 1583, 1594, 1600, 1641, 1658, 1666, 1687, 1688,
+	1700, 1717, 1718, 1719, 1721, 1782, 1783, 1805, 1812, 1839, 1840, 1862, 1863, 1864, 1875, 1914, 1947, 1971, 1972, 1974,
+	1984, 1999, 2006, 2007, 2008, 2009, 2010, 2013, 2020, 2021, 2022, 2030, 2033, 2034, 2035, 2038, 2040, 2041, 2042, 2043,
+	2045, 2046, 2047, 2065, 2068, 2099, 2100, 2106, 2111, 2119, 2126, 2135, 2144, 2160, 2170, 2179, 2190, 2191, 2196, 2200,
+	2222, 2251, 2260, 2288, 2323, 2366, 2381, 2382, 2393, 2394, 2399, 2492, 2500, 2522, 2525, 2557, 2602, 2604, 2605, 2607,
+	2608, 2638, 2701, 2702, 2710, 2718, 2725, 2800, 2809, 2811, 2875, 2909, 2910, 2920, 2968, 2998, 3003, 3005, 3006, 3007,
+	3011, 3013, 3017, 3030, 3031, 3052, 3071, 3077, 3168, 3211, 3221, 3260, 3261, 3269, 3283, 3300, 3301, 3322, 3323, 3324,
+	3325, 3333, 3351, 3367, 3369, 3370, 3371, 3372, 3390, 3404, 3476, 3493, 3517, 3527, 3546, 3551, 3580, 3659, 3737, 3766,
+	3784, 3800, 3801, 3809, 3814, 3826, 3827, 3828, 3851, 3869, 3871, 3878, 3880, 3889, 3905, 3914, 3918, 3920, 3945, 3971,
+	3995, 3998, 4002, 4003, 4004, 4005, 4006, 4111, 4125, 4126, 4129, 4224, 4242, 4279, 4321, 4343, 4443, 4445, 4446, 4449,
+	4550, 4567, 4662, 4848, 4900, 4998, 5004, 5030, 5033, 5054, 5061, 5080, 5087, 5100, 5102, 5200, 5214, 5221, 5222, 5225,
+	5226, 5269, 5280, 5298, 5405, 5414, 5431, 5440, 5500, 5510, 5544, 5550, 5560, 5566, 5633, 5678, 5679, 5718, 5730, 5801,
+	5802, 5810, 5811, 5815, 5822, 5825, 5850, 5859, 5862, 5877, 5902, 5903, 5904, 5906, 5907, 5910, 5911, 5915, 5922, 5925,
+	5950, 5952, 5959, 5960, 5961, 5962, 5963, 5987, 5988, 5989, 5998, 5999, 6005, 6006, 6007, 6009, 6025, 6059, 6100, 6101,
+	6106, 6123, 6129, 6156, 6346, 6389, 6502, 6510, 6543, 6547, 6565, 6566, 6567, 6580, 6667, 6668, 6669, 6689, 6692, 6699,
+	6779, 6788, 6789, 6792, 6839, 6881, 6901, 6969, 7001, 7002, 7004, 7007, 7019, 7025, 7100, 7103, 7106, 7200, 7201, 7402,
+	7435, 7443, 7496, 7512, 7625, 7627, 7676, 7741, 7777, 7778, 7800, 7911, 7920, 7921, 7999, 8007, 8021, 8022, 8042, 8045,
+	8083, 8086, 8087, 8088, 8089, 8090, 8093, 8099, 8100, 8180, 8181, 8192, 8193, 8194, 8200, 8222, 8254, 8290, 8291, 8292,
+	8300, 8333, 8383, 8400, 8402, 8500, 8600, 8649, 8651, 8652, 8654, 8701, 8800, 8873, 8899, 8994, 9002, 9009, 9011, 9040,
+	9050, 9071, 9080, 9081, 9091, 9099, 9101, 9103, 9110, 9111, 9200, 9207, 9220, 9290, 9415, 9418, 9485, 9500, 9502, 9503,
+	9535, 9575, 9593, 9594, 9595, 9618, 9666, 9876, 9877, 9878, 9898, 9900, 9917, 9929, 9943, 9944, 9968, 9998, 10003, 10004,
+	10009, 10012, 10024, 10025, 10082, 10180, 10215, 10243, 10566, 10616, 10617, 10621, 10626, 10628, 10629, 10778, 11110, 11111, 11967, 12000,
+	12174, 12265, 12345, 13456, 13722, 13782, 13783, 14000, 14238, 14441, 14442, 15002, 15003, 15004, 15660, 15742, 16000, 16001, 16012, 16016,
+	16018, 16080, 16113, 16992, 16993, 17877, 17988, 18040, 18101, 18988, 19101, 19283, 19315, 19350, 19780, 19801, 19842, 20000, 20005, 20031,
+	20221, 20222, 20828, 21571, 22939, 23502, 24444, 24800, 25734, 25735, 27000, 27352, 27353, 27355, 27356, 27715, 28201, 30000, 30718, 30951,
+	31038, 31337, 32773, 32774, 32775, 32776, 32777, 32778, 32779, 32780, 32781, 32782, 32783, 32784, 32785, 33354, 33899, 34571, 34572, 34573,
+	35500, 38292, 40193, 40911, 41511, 42510, 44176, 44442, 44443, 44501, 45100, 48080, 49158, 49159, 49160, 49161, 49163, 49165, 49167, 49175,
+	49176, 49400, 49999, 50001, 50002, 50003, 50006, 50300, 50389, 50500, 50636, 50800, 51103, 51493, 52673, 52822, 52848, 52869, 54045, 54328,
+	55055, 55056, 55555, 55600, 56737, 56738, 57294, 57797, 58080, 60020, 60443, 61532, 61900, 62078, 63331, 64623, 64680, 65000, 65129, 65389
+];
+
+// The 50 most frequent UDP ports, with their frequencies
+
+const UDP: &[(u16, f64)] = &[
+	(631, 0.450281), (161, 0.433467), (137, 0.365163), (123, 0.330879), (138, 0.297830),
+	(1434, 0.293184), (445, 0.253118), (135, 0.244452), (67, 0.228010), (53, 0.213496),
+	(139, 0.193701), (500, 0.163742), (68, 0.140118), (520, 0.139376), (1900, 0.136543),
+	(4500, 0.124467), (514, 0.119804), (49152, 0.116222), (162, 0.103533), (69, 0.102436),
+	(5353, 0.082302), (111, 0.067269), (49154, 0.047665), (1701, 0.044003), (998, 0.041834),
+	(996, 0.041575), (997, 0.040835), (999, 0.038917), (3283, 0.038203), (49153, 0.037855),
+	(1812, 0.033960), (136, 0.033738), (2222, 0.032472), (2049, 0.031910), (32768, 0.031231),
+	(5060, 0.030496), (1025, 0.029126), (1433, 0.025998), (3456, 0.021750), (80, 0.021422),
+	(20031, 0.019989), (1026, 0.019778), (7, 0.019406), (1646, 0.018766), (1645, 0.018545),
+	(593, 0.018145), (518, 0.017916), (2048, 0.017728), (31337, 0.017671), (515, 0.016935)
+];
+
+// The other 50 of Nmap's 100 most frequent UDP ports, by port number
+const UDP_TAIL: &[u16] = &[
+	9, 17, 19, 49, 88, 120, 158, 177, 427, 443, 497, 623, 626, 1022, 1023, 1027, 1028, 1029, 1030, 1718,
+	1719, 1813, 2000, 2223, 3703, 4444, 5000, 5632, 9200, 10000, 17185, 30718, 32769, 32771, 32815, 33281, 49156, 49181, 49182, 49185,
+	49186, 49188, 49190, 49191, 49192, 49193, 49194, 49200, 49201, 65024
+];
+
+fn table(protocol: Protocol) -> (&'static [(u16, f64)], &'static [u16]) {
+	match protocol {
+		Protocol::Tcp => (TCP, TCP_TAIL),
+		Protocol::Udp => (UDP, UDP_TAIL),
+		Protocol::Sctp | Protocol::Ip => (&[], &[])
+	}
+}
+
+// Ports of a protocol open at least ratio of the time, most frequent first
+// with a count, the first count of them, ports past the table are never picked
+// the tail has no frequencies, ratios below the last listed one pick it all
+fn top(protocol: Protocol, count: Option<usize>, ratio: Option<f64>) -> Vec<u16> {
+	let (ranked, tail) = table(protocol);
+	let mut ranked: Vec<(u16, f64)> = ranked.to_vec();
+	ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+	let lowest = ranked.last().map_or(0.0, |(_, f)| *f);
+	let mut ports: Vec<u16> = ranked.iter().filter(|(_, f)| ratio.is_none_or(|r| *f >= r)).map(|(port, _)| *port).collect();
+
+	if ratio.is_none_or(|r| r < lowest) {
+		ports.extend(tail);
+	}
+	if let Some(count) = count {
+		ports.truncate(count);
+	}
+	ports
+}
+
+// TCP and UDP ports picked by --top-ports and --port-ratio, each for its own scans
+pub fn select(count: Option<usize>, ratio: Option<f64>) -> Option<LoopIterator<PortRange>> {
+	if count.is_none() && ratio.is_none() {
+		return None;
+	}
+
+	let ranges = [Protocol::Tcp, Protocol::Udp].into_iter().flat_map(|protocol| {
+		top(protocol, count, ratio).into_iter().map(move |port| PortRange::new(port, port).with_protocol(Some(protocol)))
+	});
+	Some(LoopIterator::from(ports::merge(ranges.collect())))
+}
+
+pub fn parse_ratio(str: &str) -> Result<f64, String> {
+	match str.trim().parse::<f64>() {
+		Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+		_ => Err(format!("\"{str}\" is not a valid ratio, expected a number between 0 and 1"))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{parse_ratio, select, top};
+	use crate::iterators::Protocol;
+
+	#[test]
+	fn top_ports() {
+		assert_eq!(top(Protocol::Tcp, Some(5), None), vec![80, 23, 443, 21, 22]);
+		assert_eq!(top(Protocol::Udp, Some(2), None), vec![631, 161]);
+		assert_eq!(top(Protocol::Tcp, None, Some(0.2)), vec![80, 23, 443]);
+		assert_eq!(top(Protocol::Tcp, Some(2), Some(0.2)), vec![80, 23]);
+		assert_eq!(top(Protocol::Tcp, Some(102), None)[100..], [1000, 3001]);
+
+		// Nmap's default 1000 TCP ports, never more
+		let all = top(Protocol::Tcp, Some(70000), None);
+		assert_eq!(all.len(), 1000);
+		assert_eq!(all.iter().collect::<std::collections::HashSet<_>>().len(), 1000);
+		assert_eq!(all[0], 80);
+		assert_eq!(top(Protocol::Udp, None, None).len(), 100);
+		assert_eq!(top(Protocol::Tcp, None, Some(0.001)).len(), 1000);
+		assert_eq!(top(Protocol::Tcp, None, Some(0.0038)).len(), 99); // all but 37, the tail is less frequent
+	}
+
+	#[test]
+	fn selection() {
+		assert!(select(None, None).is_none());
+
+		let ranges: Vec<String> = select(Some(3), None).unwrap().as_slice().iter().map(|range| range.to_string()).collect();
+		assert_eq!(ranges, ["T:23-23", "T:80-80", "T:443-443", "U:137-137", "U:161-161", "U:631-631"]);
+		assert!(parse_ratio("0.5").is_ok());
+		assert!(parse_ratio("1.5").is_err());
+		assert!(parse_ratio("often").is_err());
+	}
+}
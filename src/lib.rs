@@ -1,4 +1,5 @@
 pub mod cli;
+pub mod dns;
 pub mod iterators;
 pub mod probes;
 
@@ -17,4 +18,26 @@ const ACCEPTED_ICMP_CODES: [IcmpCode; 6] = [
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
 pub const DELAY: Duration = Duration::from_millis(1);
-pub const SCAN_NUM: u16 = 6;
+pub const SCAN_NUM: u16 = 15;
+const STREAM_DEDUP_WINDOW: usize = 1 << 16;
+// IPv6 blocks wider than this would be far too long to sweep
+const IPV6_MAX_PREFIX: u32 = 96;
+// Octet ranges like "1-255.1-255.1-255.1" can't be stored as a few contiguous blocks
+const OCTET_MAX_RANGES: u64 = 1 << 18;
+const STREAM_BUFFER: usize = 1024;
+const RDNS_THREADS: usize = 16;
+const RESOLVE_THREADS: usize = 16; // system lookups of hostnames running at once
+const RDNS_TIMEOUT: Duration = Duration::from_secs(5);
+const SERVICES_PATH: &str = "/etc/services";
+const DNS_PORT: u16 = 53;
+const PROTO_PORT: u16 = 40125; // TCP, UDP and SCTP headers of PROTO scans, port 0 gets dropped
+const IDLE_PORT: u16 = 80; // of the zombie, when not given
+const IDLE_CHECKS: usize = 4; // probes telling whether a zombie will do
+const IDLE_MAX_STEP: u16 = 16;
+const IDLE_RETRIES: usize = 3; // per port, when the zombie talked to someone else meanwhile
+const IDLE_WAIT: Duration = Duration::from_millis(100); // for the target and the zombie to talk
+const FTP_PORT: u16 = 21;
+const FTP_TIMEOUT: Duration = Duration::from_secs(10); // servers wait for filtered ports themselves
+const DNS_RETRIES: usize = 2; // per server
+const DNS_MAX_INFLIGHT: usize = 256;
+pub const DNS_DEFAULT_TIMEOUT_MS: u64 = 1000;
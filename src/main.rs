@@ -2,36 +2,161 @@ use clap::Parser;
 use anyhow::{Result, anyhow};
 use mio::{Poll, Events, Token, unix::SourceFd, Interest};
 use std::{
-	time::Instant,
-	net::{Ipv4Addr, IpAddr}
+	io,
+	time::{Duration, Instant},
+	net::{Ipv4Addr, Ipv6Addr, IpAddr, SocketAddr, SocketAddrV6}
 };
 use pnet::datalink::{self, NetworkInterface};
 use socket::{Socket, SOCK_RAW, SOCK_DGRAM, htons};
-use libc::{AF_PACKET, ETH_P_ALL, AF_INET, IPPROTO_RAW};
+use libc::{AF_PACKET, ETH_P_ALL, AF_INET, AF_INET6, IPPROTO_RAW, MSG_DONTWAIT};
 
-use port_scanner::{cli, probes::{self, report::Scanner}};
+use port_scanner::{cli, dns, iterators::{LoopIterator, Protocol, ScanType}, probes::{self, connect::Connector, ftp::Bouncer, idle::Link, report::Scanner}};
 use port_scanner::DELAY;
 
+// We create two sockets, one for sending and one for receiving
+// plus one more to send IPv6 probes when there is an IPv6 address
+// tx is AF_INET because no one wants to fill MAC addresses by hand
+// rx is AF_PACKET because we can't receive ICMP, TCP and UDP on a unique raw socket
+// and using three sockets would be harder to manage
+// this means we will receive more packets though
+struct RawSockets {
+	tx: Socket,
+	tx6: Option<Socket>,
+	rx: Socket
+}
+
+impl RawSockets {
+	fn open(ipv6: bool) -> io::Result<Self> {
+		Ok(Self {
+			tx: Socket::new(AF_INET, SOCK_RAW, IPPROTO_RAW)?,
+			tx6: match ipv6 {
+				true => Some(Socket::new(AF_INET6, SOCK_RAW, IPPROTO_RAW)?),
+				false => None
+			},
+			rx: Socket::new(AF_PACKET, SOCK_DGRAM, htons(ETH_P_ALL as u16).into())?
+		})
+	}
+
+	// Readiness is edge-triggered, packets left in the queue
+	// would only be read once another one comes in
+	fn drain(&self, buffer: &mut [u8], mut handle: impl FnMut(&[u8])) -> io::Result<()> {
+		loop {
+			match self.rx.recv_into(buffer, MSG_DONTWAIT) {
+				Ok(bytes) => handle(&buffer[..bytes]),
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+				Err(e) => return Err(e)
+			}
+		}
+	}
+}
+
+// Idle scans wait for answers one at a time
+struct IdleLink<'a> {
+	raw: &'a RawSockets,
+	poll: &'a mut Poll,
+	events: &'a mut Events,
+	buffer: &'a mut [u8]
+}
+
+impl Link for IdleLink<'_> {
+	fn send(&mut self, packet: &[u8], destination: SocketAddr) -> io::Result<()> {
+		self.raw.tx.sendto(packet, 0, &destination).map(|_| ())
+	}
+
+	fn receive(&mut self, timeout: Duration, handle: &mut dyn FnMut(&[u8]) -> bool) -> io::Result<()> {
+		let start = Instant::now();
+		let mut taken = false;
+
+		while let Some(left) = timeout.checked_sub(start.elapsed()) {
+			self.poll.poll(self.events, Some(left))?;
+			self.raw.drain(self.buffer, |packet| taken |= handle(packet))?;
+			if taken {
+				break ;
+			}
+		}
+		Ok(())
+	}
+}
+
 fn main() -> Result<()> {
-	let args = cli::Args::parse();
-	let (_, source) = lookup_interfaces()?;
+	let mut args = cli::Args::parse();
+	let reverse_dns = args.reverse_dns;
+	let dns_client = dns::client(&args);
+	let output = args.output.clone();
+	let custom = (args.scanflags_silence, args.scanflags_rst);
+	let relay = args.ftp_relay.clone();
+	let (_, source, source6) = lookup_interfaces()?;
+
+	// The kernel sends connect scans for us, and FTP servers send bounce scans
+	let raw = match args.scans.as_slice().iter().all(|scan| matches!(scan, ScanType::CONNECT | ScanType::BOUNCE)) {
+		true => None,
+		false => match RawSockets::open(source6.is_some()) {
+			Ok(raw) => Some(raw),
+			// Only TCP scans have a CONNECT counterpart, the others are left out
+			Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+				let (mut kept, mut tcp, mut dropped) = (vec![], vec![], vec![]);
+				for scan in args.scans.as_slice().iter().copied() {
+					match scan {
+						ScanType::CONNECT | ScanType::BOUNCE => kept.push(scan),
+						ScanType::IDLE => dropped.push(scan),
+						_ if scan.protocol() == Protocol::Tcp => tcp.push(scan),
+						_ => dropped.push(scan)
+					}
+				}
+				if kept.is_empty() && tcp.is_empty() {
+					return Err(e.into());
+				}
+				if !tcp.is_empty() {
+					eprintln!("warning: raw sockets are not allowed ({e}), running a CONNECT scan instead of {}", LoopIterator::from(tcp));
+					if !kept.contains(&ScanType::CONNECT) {
+						kept.insert(0, ScanType::CONNECT);
+					}
+				}
+				if !dropped.is_empty() {
+					eprintln!("warning: raw sockets are not allowed ({e}), skipping {}", LoopIterator::from(dropped));
+				}
+				args.scans = LoopIterator::from(kept);
+				(args.scanflags, args.zombie) = (None, None);
+				None
+			},
+			Err(e) => return Err(e.into())
+		}
+	};
 	let mut probes = probes::ProbeBuilder::new(args, source)?;
+	if let Some(source6) = source6 {
+		probes.set_source_v6(source6);
+	}
+	if let Some(seed) = probes.seed() {
+		eprintln!("probes are sent in random order, use --seed {seed} to repeat it");
+	}
 
-	// We create two sockets, one for sending and one for receiving
-	// tx is AF_INET because no one wants to fill MAC addresses by hand
-	// rx is AF_PACKET because we can't receive ICMP, TCP and UDP on a unique raw socket
-	// and using three sockets would be harder to manage
-	// this means we will receive more packets though
 	const SOCKET: Token = Token(0);
-	let tx = Socket::new(AF_INET, SOCK_RAW, IPPROTO_RAW)?;
-	let rx = Socket::new(AF_PACKET, SOCK_DGRAM, htons(ETH_P_ALL as u16).into())?;
 	let buffer = &mut [0u8; 8192];
 
 	let mut poll = Poll::new()?;
 	let mut events = Events::with_capacity(1024);
-	poll.registry().register(&mut SourceFd(&rx.fileno()), SOCKET, Interest::READABLE)?;
+	if let Some(raw) = raw.as_ref() {
+		poll.registry().register(&mut SourceFd(&raw.rx.fileno()), SOCKET, Interest::READABLE)?;
+	}
+	let mut connector = Connector::new(SOCKET.0 + 1);
 
 	let mut scanner = Scanner::new();
+	scanner.read_custom_as(custom.0, custom.1);
+	if reverse_dns {
+		scanner.enable_reverse_dns(dns_client);
+	}
+
+	// Zombies and FTP servers are checked before anything is sent through them
+	let zombie = probes.zombie();
+	if let (Some(zombie), Some(raw)) = (zombie.as_ref(), raw.as_ref()) {
+		zombie.check(&mut IdleLink { raw, poll: &mut poll, events: &mut events, buffer: &mut buffer[..] }, &mut scanner)?;
+	}
+	let mut bouncer = match relay {
+		Some(relay) => Some(Bouncer::connect(relay)?),
+		None => None
+	};
+	let mut deferred = vec![];
+
 	let mut time = Instant::now();
 	let mut wait = false;
 
@@ -41,12 +166,29 @@ fn main() -> Result<()> {
 	loop {
 		if time.elapsed() > DELAY || !wait {
 			if let Some(packet) = probes.next() {
-				tx.sendto(&packet.data, 0, &packet.destination)?;
+				if matches!(packet.scan, ScanType::IDLE | ScanType::BOUNCE) {
+					deferred.push(packet); // sent one at a time, once the others are done
+					continue ;
+				}
+				let answer = match (packet.scan, packet.destination, raw.as_ref()) {
+					(ScanType::CONNECT, _, _) | (_, _, None) => connector.start(poll.registry(), &packet)?,
+					(_, SocketAddr::V6(destination), Some(RawSockets { tx6: Some(tx6), .. })) => {
+						sendto_v6(tx6, &packet.data, destination)?;
+						None
+					},
+					(_, _, Some(raw)) => {
+						raw.tx.sendto(&packet.data, 0, &packet.destination)?;
+						None
+					}
+				};
 				scanner.add(packet);
-			} else {
+				if let Some(answer) = answer {
+					scanner.record(answer);
+				}
+			} else if !probes.is_waiting() {
 				break ;
 			}
-			time = Instant::now();
+			time = Instant::now(); // when waiting for streamed targets, tries again on the next tick
 			wait = true; // avoid needlessly waiting DELAY for the first probe
 		}
 
@@ -54,12 +196,15 @@ fn main() -> Result<()> {
 		poll.poll(&mut events, Some(delay))?;
 
 		for ev in events.iter() {
-			if ev.is_readable() {
-				let bytes = rx.recv_into(buffer, 0)?;
-				let packet = &buffer[..bytes];
-				scanner.update(packet);
+			match (ev.token(), raw.as_ref()) {
+				(SOCKET, Some(raw)) if ev.is_readable() => raw.drain(buffer, |packet| scanner.update(packet))?,
+				(token, _) => if let Some(answer) = connector.ready(poll.registry(), token) {
+					scanner.record(answer);
+				}
 			}
 		}
+		scanner.expire(); // keeps state down to the probes in flight
+		connector.expire(poll.registry());
 	}
 
 	// Second loop
@@ -67,32 +212,111 @@ fn main() -> Result<()> {
 	// ends when we caught'em all
 	// or if they're all timed out
 	while !scanner.is_complete() {
+		connector.expire(poll.registry());
 		poll.poll(&mut events, Some(DELAY))?;
 
 		for ev in events.iter() {
-			if ev.is_readable() {
-				let bytes = rx.recv_into(buffer, 0)?;
-				let packet = &buffer[..bytes];
-				scanner.update(packet);
+			match (ev.token(), raw.as_ref()) {
+				(SOCKET, Some(raw)) if ev.is_readable() => raw.drain(buffer, |packet| scanner.update(packet))?,
+				(token, _) => if let Some(answer) = connector.ready(poll.registry(), token) {
+					scanner.record(answer);
+				}
 			}
 		}
 	}
 
-	scanner.print();
+	// Idle probes wait for the zombie to answer before and after them
+	// bounce probes for the FTP server to tell how the transfer went
+	let mut link = raw.as_ref().map(|raw| IdleLink { raw, poll: &mut poll, events: &mut events, buffer: &mut buffer[..] });
+	let mut failure = None;
+	for probe in deferred.into_iter() {
+		let answer = match (probe.scan, zombie.as_ref(), link.as_mut(), bouncer.as_mut()) {
+			(ScanType::IDLE, Some(zombie), Some(link), _) => zombie.scan(link, &probe, &mut scanner)?,
+			(ScanType::BOUNCE, _, _, Some(bouncer)) => match bouncer.probe(&probe) {
+				Ok(answer) => Some(answer),
+				Err(e) => {
+					failure = Some(e); // results so far are still worth printing
+					break ;
+				}
+			},
+			_ => None
+		};
+		let (scan, target) = (probe.scan, probe.destination);
+		scanner.add(probe);
+		match (answer, zombie.as_ref()) {
+			(Some(answer), _) => scanner.record(answer),
+			(None, Some(zombie)) if scan == ScanType::IDLE => eprintln!("warning: zombie {} did not stay idle while probing {target}", zombie.address()),
+			_ => {}
+		}
+	}
+
+	scanner.print(output.as_deref())?;
+	if probes.excluded() > 0 {
+		println!("{} target(s) excluded", probes.excluded());
+	}
+	for (range, reason) in probes.skipped_targets().into_iter() {
+		println!("{range} skipped: {reason} (--allow-special to scan it)");
+	}
+	for (skipped, family) in probes.skipped().into_iter().zip(["IPv4", "IPv6"]) {
+		if skipped > 0 {
+			eprintln!("warning: {skipped} {family} probe(s) not sent, no {family} address to send them from");
+		}
+	}
 
-	Ok(())
+	failure.map_or(Ok(()), Err)
 }
 
-fn lookup_interfaces() -> Result<(NetworkInterface, Ipv4Addr)> {
+// Also returns the first global IPv6 address of the interface, if any
+// link-local addresses can't reach anything beyond the link
+// interfaces with an IPv4 address are preferred, IPv6-only ones come next
+fn lookup_interfaces() -> Result<(NetworkInterface, Option<Ipv4Addr>, Option<Ipv6Addr>)> {
+	let mut ipv6_only = None;
 	for ifa in datalink::interfaces().into_iter() {
 		if !ifa.is_up() || ifa.is_loopback() {
 			continue ;
 		}
 	
-		if let Some(IpAddr::V4(ip)) = ifa.ips.iter().map(|net| net.ip()).find(|ip| ip.is_ipv4()) {
-			return Ok((ifa, ip));
+		let ip = ifa.ips.iter().find_map(|net| match net.ip() {
+			IpAddr::V4(ip) => Some(ip),
+			_ => None
+		});
+		let ip6 = ifa.ips.iter().find_map(|net| match net.ip() {
+			IpAddr::V6(ip6) if ip6.segments()[0] & 0xffc0 != 0xfe80 => Some(ip6),
+			_ => None
+		});
+		match (ip, ip6) {
+			(Some(_), _) => return Ok((ifa, ip, ip6)),
+			(None, Some(_)) if ipv6_only.is_none() => ipv6_only = Some((ifa, ip, ip6)),
+			_ => {}
 		}
 	}
 
-	Err(anyhow!("no suitable device found"))
+	ipv6_only.ok_or_else(|| anyhow!("no suitable device found"))
+}
+
+// The socket crate only handles IPv4 destinations
+fn sendto_v6(socket: &Socket, data: &[u8], destination: SocketAddrV6) -> io::Result<usize> {
+	// SAFETY: sockaddr_in6 is plain old data, all zeroes is a valid value
+	let mut addr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+	addr.sin6_family = AF_INET6 as libc::sa_family_t;
+	addr.sin6_addr.s6_addr = destination.ip().octets();
+	addr.sin6_scope_id = destination.scope_id();
+	// sin6_port stays 0, raw sockets would take it for a protocol number
+
+	// SAFETY: data and addr outlive the call and their lengths are passed along
+	let sent = unsafe {
+		libc::sendto(
+			socket.fileno(),
+			data.as_ptr() as *const libc::c_void,
+			data.len(),
+			0,
+			&addr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+			std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+		)
+	};
+
+	match sent {
+		-1 => Err(io::Error::last_os_error()),
+		n => Ok(n as usize)
+	}
 }
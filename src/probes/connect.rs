@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
+use mio::{Interest, Registry, Token, net::TcpStream};
+use pnet::packet::icmp::IcmpTypes;
+use pnet::packet::icmp::destination_unreachable::IcmpCodes;
+use pnet::packet::tcp::TcpFlags;
+
+use super::Probe;
+use super::response::{Response, ResponseKind};
+use crate::DEFAULT_TIMEOUT;
+
+// What a raw SYN scan would have seen, so that connect results are read the same way
+// None when the error says nothing about the port
+fn connect_kind(error: Option<&io::Error>) -> Option<ResponseKind> {
+	let error = match error {
+		None => return Some(ResponseKind::Tcp { flags: TcpFlags::SYN | TcpFlags::ACK, window: 0 }),
+		Some(e) => e
+	};
+
+	match (error.kind(), error.raw_os_error()) {
+		(io::ErrorKind::ConnectionRefused, _) => Some(ResponseKind::Tcp { flags: TcpFlags::RST, window: 0 }),
+		(_, Some(libc::EHOSTUNREACH | libc::ENETUNREACH)) => Some(ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, IcmpCodes::DestinationHostUnreachable)),
+		(_, Some(libc::EACCES | libc::EPERM)) => Some(ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, IcmpCodes::CommunicationAdministrativelyProhibited)),
+		(io::ErrorKind::TimedOut, _) => Some(ResponseKind::NoResponse),
+		_ => None
+	}
+}
+
+/*
+** TCP connect scan, works without raw sockets
+** each probe is a non-blocking connect() registered in the caller's poll
+** connections are dropped as soon as they are established
+** or when they take longer than the other probes to answer
+*/
+pub struct Connector {
+	streams: HashMap<Token, (TcpStream, SocketAddr, u16)>,
+	started: VecDeque<(Instant, Token)>,
+	next: usize
+}
+
+impl Connector {
+	// Tokens from first on are taken, the ones below are left to the caller
+	pub fn new(first: usize) -> Self {
+		Self { streams: HashMap::new(), started: VecDeque::new(), next: first }
+	}
+
+	// Only answers when connect() fails right away
+	pub fn start(&mut self, registry: &Registry, probe: &Probe) -> io::Result<Option<Response>> {
+		let mut stream = match TcpStream::connect(probe.destination) {
+			Ok(stream) => stream,
+			Err(e) => return match connect_kind(Some(&e)) {
+				Some(kind) => Ok(Some(response(probe.destination, probe.source_port, kind))),
+				None => Err(e)
+			}
+		};
+
+		let token = Token(self.next);
+		self.next += 1;
+		registry.register(&mut stream, token, Interest::WRITABLE)?;
+		self.streams.insert(token, (stream, probe.destination, probe.source_port));
+		self.started.push_back((Instant::now(), token));
+		Ok(None)
+	}
+
+	// Writable means the handshake is over, one way or another
+	pub fn ready(&mut self, registry: &Registry, token: Token) -> Option<Response> {
+		let (stream, _, _) = self.streams.get(&token)?;
+		let result = match stream.take_error() {
+			Ok(Some(e)) | Err(e) => Err(e),
+			Ok(None) => match stream.peer_addr() {
+				Ok(_) => Ok(()),
+				Err(e) if e.kind() == io::ErrorKind::NotConnected => return None, // spurious wakeup
+				Err(e) => Err(e)
+			}
+		};
+
+		let (mut stream, destination, probe_id) = self.streams.remove(&token)?;
+		let _ = registry.deregister(&mut stream);
+		connect_kind(result.as_ref().err()).map(|kind| response(destination, probe_id, kind))
+	}
+
+	// Closes connections that are still pending after the timeout
+	// the scanner marks them as unanswered on its own
+	pub fn expire(&mut self, registry: &Registry) {
+		while let Some(&(time, token)) = self.started.front() {
+			if time.elapsed() <= DEFAULT_TIMEOUT {
+				break ;
+			}
+			self.started.pop_front();
+
+			if let Some((mut stream, _, _)) = self.streams.remove(&token) {
+				let _ = registry.deregister(&mut stream);
+			}
+		}
+	}
+}
+
+fn response(origin: SocketAddr, probe_id: u16, kind: ResponseKind) -> Response {
+	Response { origin, probe_id, kind, time: Instant::now(), ip_id: None }
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+	use std::time::Duration;
+	use mio::{Events, Poll};
+	use pnet::packet::tcp::TcpFlags;
+	use super::Connector;
+	use crate::iterators::ScanType;
+	use crate::probes::Probe;
+	use crate::probes::response::{Response, ResponseKind};
+
+	fn connect(destination: SocketAddr) -> Response {
+		let mut poll = Poll::new().unwrap();
+		let mut events = Events::with_capacity(16);
+		let mut connector = Connector::new(1);
+		let probe = Probe { data: vec![], destination, source_port: 40006, scan: ScanType::CONNECT, hostnames: None };
+
+		if let Some(response) = connector.start(poll.registry(), &probe).unwrap() {
+			return response;
+		}
+		loop {
+			poll.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+			for ev in events.iter() {
+				if let Some(response) = connector.ready(poll.registry(), ev.token()) {
+					return response;
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn connect_open_and_closed() {
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let open = listener.local_addr().unwrap();
+		let response = connect(open);
+		assert_eq!(response.origin, open);
+		assert_eq!(response.probe_id, 40006);
+		assert!(matches!(response.kind, ResponseKind::Tcp { flags, .. } if flags == TcpFlags::SYN | TcpFlags::ACK));
+
+		drop(listener); // nothing listens there anymore
+		assert!(matches!(connect(open).kind, ResponseKind::Tcp { flags: TcpFlags::RST, .. }));
+	}
+}
@@ -0,0 +1,263 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs};
+use std::time::Instant;
+use anyhow::{Result, anyhow};
+
+use super::Probe;
+use super::response::{Response, ResponseKind};
+use crate::{FTP_PORT, FTP_TIMEOUT};
+
+// FTP server bounce scans go through
+#[derive(Clone, Debug)]
+pub struct Relay {
+	pub address: SocketAddr,
+	user: String,
+	password: String
+}
+
+// Accepts "[user[:password]@]host[:port]", anonymous login and port 21 by default
+pub fn parse_relay(str: &str) -> Result<Relay, String> {
+	let str = str.trim();
+	let error = || format!("\"{str}\" is not a valid FTP server, expected [user[:password]@]host[:port]");
+	let (user, password, server) = match str.rsplit_once('@') {
+		Some((credentials, server)) => {
+			let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+			(user, password, server)
+		},
+		None => ("anonymous", "anonymous@", str)
+	};
+	if user.is_empty() {
+		return Err(error());
+	}
+
+	// IPv6 addresses only take a port between brackets
+	let (host, port) = match server.rsplit_once(':') {
+		Some((host, port)) if !host.contains(':') || host.ends_with(']') => (host, port.parse::<u16>().map_err(|_| error())?),
+		_ => (server, FTP_PORT)
+	};
+	let address = (host.trim_matches(['[', ']']), port).to_socket_addrs().map_err(|_| error())?.next().ok_or_else(error)?;
+
+	Ok(Relay { address, user: user.to_string(), password: password.to_string() })
+}
+
+/*
+** FTP bounce scan, through a server that takes any address in PORT commands
+** the server is told to send a listing to each port in turn
+** and tells whether it could open the data connection there
+*/
+pub struct Bouncer {
+	relay: Relay,
+	session: Option<BufReader<TcpStream>>
+}
+
+impl Bouncer {
+	// Logs in right away, so that a wrong server or wrong credentials stop the scan before it starts
+	pub fn connect(relay: Relay) -> Result<Self> {
+		let session = login(&relay)?;
+		Ok(Self { relay, session: Some(session) })
+	}
+
+	pub fn probe(&mut self, probe: &Probe) -> Result<Response> {
+		let target = match probe.destination {
+			SocketAddr::V4(target) => target,
+			SocketAddr::V6(_) => return Err(anyhow!("PORT commands only take IPv4 addresses"))
+		};
+		let mut session = match self.session.take() {
+			Some(session) => session,
+			None => login(&self.relay)?
+		};
+
+		// After a timeout the late reply would be taken for the next one, so we log in again
+		let kind = match bounce(&mut session, target) {
+			Ok(kind) => {
+				self.session = Some(session);
+				kind
+			},
+			Err(e) if is_timeout(&e) => ResponseKind::NoResponse,
+			Err(e) => return Err(e)
+		};
+
+		Ok(Response { origin: probe.destination, probe_id: probe.source_port, kind, time: Instant::now(), ip_id: None })
+	}
+}
+
+fn login(relay: &Relay) -> Result<BufReader<TcpStream>> {
+	let stream = TcpStream::connect_timeout(&relay.address, FTP_TIMEOUT).map_err(|e| anyhow!("FTP server {}: {e}", relay.address))?;
+	stream.set_read_timeout(Some(FTP_TIMEOUT))?;
+	let mut session = BufReader::new(stream);
+
+	// 120 means the server will be ready later
+	let mut greeting = reply(&mut session)?;
+	while greeting.0 == 120 {
+		greeting = reply(&mut session)?;
+	}
+	if greeting.0 != 220 {
+		return Err(anyhow!("FTP server {} is not available ({})", relay.address, greeting.1));
+	}
+
+	let mut answer = command(&mut session, &format!("USER {}", relay.user))?;
+	if answer.0 == 331 {
+		answer = command(&mut session, &format!("PASS {}", relay.password))?;
+	}
+	match answer {
+		(230, _) => Ok(session),
+		(_, text) => Err(anyhow!("FTP server {} refused the login ({text})", relay.address))
+	}
+}
+
+// Reply to LIST once the server tried to reach the target
+// servers often refuse PORT for some targets only (ports below 1024 for instance)
+// which tells nothing about the port, as if the server had not answered
+fn bounce(session: &mut BufReader<TcpStream>, target: SocketAddrV4) -> Result<ResponseKind> {
+	let [a, b, c, d] = target.ip().octets();
+	let [high, low] = target.port().to_be_bytes();
+	let (code, _) = command(session, &format!("PORT {a},{b},{c},{d},{high},{low}"))?;
+	if code / 100 != 2 {
+		return Ok(ResponseKind::NoResponse);
+	}
+
+	// Preliminary replies are followed by the outcome of the transfer
+	let (code, _) = command(session, "LIST")?;
+	if code / 100 == 1 {
+		reply(session)?;
+	}
+	Ok(ResponseKind::Ftp(code))
+}
+
+fn command(session: &mut BufReader<TcpStream>, line: &str) -> Result<(u16, String)> {
+	session.get_mut().write_all(format!("{line}\r\n").as_bytes())?;
+	reply(session)
+}
+
+// Code and last line of a reply, multiline replies go from "123-" to "123 "
+fn reply(session: &mut BufReader<TcpStream>) -> Result<(u16, String)> {
+	let mut multiline: Option<String> = None;
+	loop {
+		let mut line = String::new();
+		if session.read_line(&mut line)? == 0 {
+			return Err(anyhow!("FTP server closed the connection"));
+		}
+		let line = line.trim_end();
+		let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+
+		match (code, multiline.as_deref()) {
+			(Some(_), None) if line.as_bytes().get(3) == Some(&b'-') => multiline = Some(format!("{} ", &line[..3])),
+			(Some(code), None) => return Ok((code, line.to_string())),
+			(Some(code), Some(end)) if line.starts_with(end) => return Ok((code, line.to_string())),
+			(None, None) => return Err(anyhow!("unexpected FTP reply \"{line}\"")),
+			_ => continue
+		}
+	}
+}
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+	matches!(error.downcast_ref::<io::Error>().map(io::Error::kind), Some(io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::{BufRead, BufReader, Write};
+	use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+	use std::time::Duration;
+	use super::{parse_relay, Bouncer};
+	use crate::iterators::ScanType;
+	use crate::probes::Probe;
+	use crate::probes::response::ResponseKind;
+
+	// Just enough of an FTP server, bounces to 10.9.9.9 are refused
+	fn stand_in() -> SocketAddr {
+		let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let address = listener.local_addr().unwrap();
+
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+				let (mut user, mut data) = (String::new(), None);
+				stream.write_all(b"220-Welcome\r\n220-to the stand-in\r\n220 Ready\r\n").unwrap();
+
+				while let Some(Ok(line)) = lines.next() {
+					let (verb, argument) = line.split_once(' ').unwrap_or((&line, ""));
+					let answer = match verb {
+						"USER" => {
+							user = argument.to_string();
+							"331 Password please.\r\n".to_string()
+						},
+						"PASS" if user == "bob" && argument != "secret" => "530 Login incorrect.\r\n".to_string(),
+						"PASS" => "230 Login successful.\r\n".to_string(),
+						"PORT" => {
+							let numbers: Vec<u8> = argument.split(',').map(|n| n.parse().unwrap()).collect();
+							let target = SocketAddrV4::new(Ipv4Addr::new(numbers[0], numbers[1], numbers[2], numbers[3]), u16::from_be_bytes([numbers[4], numbers[5]]));
+							data = Some(target);
+							match target.ip().octets() {
+								[10, 9, 9, 9] => "500 Illegal PORT command.\r\n".to_string(),
+								_ => "200 PORT command successful.\r\n".to_string()
+							}
+						},
+						"LIST" => match TcpStream::connect_timeout(&data.unwrap().into(), Duration::from_secs(1)) {
+							Ok(mut listing) => {
+								stream.write_all(b"150 Here comes the directory listing.\r\n").unwrap();
+								let _ = listing.write_all(b"-rw-r--r-- 1 ftp ftp 0 Jan 1 00:00 README\r\n");
+								"226 Directory send OK.\r\n".to_string()
+							},
+							Err(_) => "425 Failed to establish connection.\r\n".to_string()
+						},
+						_ => "502 Command not implemented.\r\n".to_string()
+					};
+					stream.write_all(answer.as_bytes()).unwrap();
+				}
+			}
+		});
+
+		address
+	}
+
+	fn probe(destination: SocketAddr) -> Probe {
+		Probe { data: vec![], destination, source_port: 40014, scan: ScanType::BOUNCE, hostnames: None }
+	}
+
+	#[test]
+	fn bounce_scan() {
+		let open = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap(); // nothing listens there anymore
+		let mut bouncer = Bouncer::connect(parse_relay(&stand_in().to_string()).unwrap()).unwrap();
+
+		let response = bouncer.probe(&probe(open.local_addr().unwrap())).unwrap();
+		assert_eq!(response.origin, open.local_addr().unwrap());
+		assert_eq!(response.probe_id, 40014);
+		assert!(matches!(response.kind, ResponseKind::Ftp(150)));
+		assert!(matches!(bouncer.probe(&probe(closed)).unwrap().kind, ResponseKind::Ftp(425)));
+		assert!(matches!(bouncer.probe(&probe(open.local_addr().unwrap())).unwrap().kind, ResponseKind::Ftp(150)));
+
+		// Targets the server won't bounce to tell nothing, the next ones are still scanned
+		assert!(matches!(bouncer.probe(&probe("10.9.9.9:80".parse().unwrap())).unwrap().kind, ResponseKind::NoResponse));
+		assert!(matches!(bouncer.probe(&probe(closed)).unwrap().kind, ResponseKind::Ftp(425)));
+		assert!(bouncer.probe(&probe("[::1]:80".parse().unwrap())).is_err());
+	}
+
+	#[test]
+	fn ftp_login() {
+		let server = stand_in();
+		assert!(Bouncer::connect(parse_relay(&format!("bob:secret@{server}")).unwrap()).is_ok());
+		assert!(Bouncer::connect(parse_relay(&format!("bob:wrong@{server}")).unwrap()).is_err());
+
+		let nothing = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap();
+		assert!(Bouncer::connect(parse_relay(&nothing.to_string()).unwrap()).is_err());
+	}
+
+	#[test]
+	fn relay_addresses() {
+		let relay = parse_relay("10.0.0.5").unwrap();
+		assert_eq!(relay.address, "10.0.0.5:21".parse::<SocketAddr>().unwrap());
+		assert_eq!((relay.user.as_str(), relay.password.as_str()), ("anonymous", "anonymous@"));
+
+		let relay = parse_relay("bob:p@ss:word@10.0.0.5:2121").unwrap();
+		assert_eq!(relay.address, "10.0.0.5:2121".parse::<SocketAddr>().unwrap());
+		assert_eq!((relay.user.as_str(), relay.password.as_str()), ("bob", "p@ss:word"));
+
+		assert_eq!(parse_relay("alice@::1").unwrap().address, "[::1]:21".parse::<SocketAddr>().unwrap());
+		assert_eq!(parse_relay("[::1]:2121").unwrap().address, "[::1]:2121".parse::<SocketAddr>().unwrap());
+		assert!(parse_relay(":secret@10.0.0.5").is_err());
+		assert!(parse_relay("10.0.0.5:ftp").is_err());
+	}
+}
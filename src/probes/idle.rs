@@ -0,0 +1,240 @@
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use pnet::packet::tcp::TcpFlags;
+
+use super::Probe;
+use super::report::Scanner;
+use super::response::{Response, ResponseKind};
+use crate::{DEFAULT_TIMEOUT, IDLE_CHECKS, IDLE_MAX_STEP, IDLE_PORT, IDLE_RETRIES, IDLE_WAIT};
+
+// Raw sockets, as far as idle scans are concerned
+pub trait Link {
+	fn send(&mut self, packet: &[u8], destination: SocketAddr) -> io::Result<()>;
+
+	// Hands incoming packets over until handle takes one or time runs out
+	fn receive(&mut self, timeout: Duration, handle: &mut dyn FnMut(&[u8]) -> bool) -> io::Result<()>;
+}
+
+/*
+** Idle scan, through a zombie that numbers the packets it sends one after the other
+** SYNs are sent to targets from the zombie's address, so targets answer the zombie
+** which sends a RST to ports that answered with a SYN/ACK and keeps quiet otherwise
+** its IP-ID, read before and after, then moves by 2 for open ports and by 1 for the others
+*/
+pub struct Zombie {
+	probe: Probe // SYN/ACK from us, answered by a RST whatever the port
+}
+
+impl Zombie {
+	pub fn new(probe: Probe) -> Self {
+		Self { probe }
+	}
+
+	pub fn address(&self) -> SocketAddr {
+		self.probe.destination
+	}
+
+	// IP-ID of the zombie, when the packet is its answer to our probe
+	fn ip_id(&self, packet: &[u8]) -> Option<u16> {
+		let response = Response::try_from(packet).ok()?;
+		match response.kind {
+			ResponseKind::Tcp { flags, .. } if flags & TcpFlags::RST != 0 => {
+				(response.origin == self.probe.destination && response.probe_id == self.probe.source_port).then_some(response.ip_id?)
+			},
+			_ => None
+		}
+	}
+
+	// Other packets that come in meanwhile go to the scanner
+	fn query(&self, link: &mut dyn Link, scanner: &mut Scanner) -> io::Result<Option<u16>> {
+		link.send(&self.probe.data, self.probe.destination)?;
+
+		let mut ip_id = None;
+		link.receive(DEFAULT_TIMEOUT, &mut |packet| match self.ip_id(packet) {
+			Some(id) => {
+				ip_id = Some(id);
+				true
+			},
+			None => {
+				scanner.update(packet);
+				false
+			}
+		})?;
+		Ok(ip_id)
+	}
+
+	// Zombies must answer every probe, with IP-IDs that go up by small steps
+	// random or constant IP-IDs tell nothing about what the zombie sent
+	pub fn check(&self, link: &mut dyn Link, scanner: &mut Scanner) -> Result<()> {
+		let mut ids = vec![];
+		for _ in 0..IDLE_CHECKS {
+			match self.query(link, scanner)? {
+				Some(id) => ids.push(id),
+				None => return Err(anyhow!("zombie {} does not answer", self.address()))
+			}
+		}
+
+		match incremental(&ids) {
+			true => Ok(()),
+			false => Err(anyhow!("zombie {} is not usable, its IP-IDs are not incremental ({ids:?})", self.address()))
+		}
+	}
+
+	// Sends the probe and reads how far the zombie's IP-ID moved
+	// None when the zombie kept talking to other hosts meanwhile
+	pub fn scan(&self, link: &mut dyn Link, probe: &Probe, scanner: &mut Scanner) -> io::Result<Option<Response>> {
+		for _ in 0..IDLE_RETRIES {
+			let before = match self.query(link, scanner)? {
+				Some(id) => id,
+				None => continue
+			};
+
+			link.send(&probe.data, probe.destination)?;
+			link.receive(IDLE_WAIT, &mut |packet| {
+				scanner.update(packet);
+				false
+			})?;
+
+			let after = match self.query(link, scanner)? {
+				Some(id) => id,
+				None => continue
+			};
+			let step = after.wrapping_sub(before);
+			if (1..=2).contains(&step) {
+				return Ok(Some(Response { origin: probe.destination, probe_id: probe.source_port, kind: ResponseKind::IpId(step), time: Instant::now(), ip_id: None }));
+			}
+		}
+
+		Ok(None)
+	}
+}
+
+// Every IP-ID is a little past the previous one
+pub fn incremental(ids: &[u16]) -> bool {
+	ids.len() > 1 && ids.windows(2).all(|pair| (1..=IDLE_MAX_STEP).contains(&pair[1].wrapping_sub(pair[0])))
+}
+
+// Accepts "10.0.0.9", "10.0.0.9:443" or a hostname with an optional port
+pub fn parse_zombie(str: &str) -> Result<SocketAddrV4, String> {
+	let str = str.trim();
+	let error = || format!("\"{str}\" is not a valid zombie, expected an IPv4 address or hostname and an optional port");
+	let spec = match str.rsplit_once(':') {
+		Some((host, port)) => (host, port.parse::<u16>().map_err(|_| error())?),
+		None => (str, IDLE_PORT)
+	};
+
+	spec.to_socket_addrs().map_err(|_| error())?.find_map(|addr| match addr {
+		SocketAddr::V4(addr) => Some(addr),
+		SocketAddr::V6(_) => None
+	}).ok_or_else(error)
+}
+
+#[cfg(test)]
+mod test {
+	use std::collections::VecDeque;
+	use std::io;
+	use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+	use std::time::Duration;
+	use super::{incremental, parse_zombie, Link, Zombie};
+	use crate::iterators::ScanType;
+	use crate::probes::Probe;
+	use crate::probes::report::Scanner;
+	use crate::probes::response::ResponseKind;
+
+	const ZOMBIE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 9);
+	const TARGET: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+	// Answers zombie probes with its IP-ID, which open ports of the target bump once more
+	struct Lab {
+		ip_id: u16,
+		step: u16, // between two answers, when nothing else happens
+		open: Vec<u16>,
+		answers: VecDeque<Vec<u8>>
+	}
+
+	impl Lab {
+		fn rst(&self) -> Vec<u8> {
+			let mut packet = vec![0x45, 0, 0, 40];
+			packet.extend(self.ip_id.to_be_bytes());
+			packet.extend([0, 0, 64, 6, 0, 0]);
+			packet.extend(ZOMBIE.octets());
+			packet.extend([10, 0, 0, 1, 0, 80, 0x9c, 0x4e]); // to port 40014
+			packet.extend([0, 0, 0, 0, 0, 0, 0, 0, 5 << 4, 0x04, 0, 0, 0, 0, 0, 0]);
+			packet
+		}
+	}
+
+	impl Link for Lab {
+		fn send(&mut self, _: &[u8], destination: SocketAddr) -> io::Result<()> {
+			match destination {
+				SocketAddr::V4(addr) if *addr.ip() == ZOMBIE => {
+					self.answers.push_back(self.rst());
+					self.ip_id = self.ip_id.wrapping_add(self.step);
+				},
+				_ if self.open.contains(&destination.port()) => self.ip_id = self.ip_id.wrapping_add(1),
+				_ => {}
+			}
+			Ok(())
+		}
+
+		fn receive(&mut self, _: Duration, handle: &mut dyn FnMut(&[u8]) -> bool) -> io::Result<()> {
+			while let Some(packet) = self.answers.pop_front() {
+				if handle(&packet) {
+					break ;
+				}
+			}
+			Ok(())
+		}
+	}
+
+	fn zombie() -> Zombie {
+		let destination = SocketAddrV4::new(ZOMBIE, 80).into();
+		Zombie::new(Probe { data: vec![], destination, source_port: 40014, scan: ScanType::IDLE, hostnames: None })
+	}
+
+	fn probe(port: u16) -> Probe {
+		Probe { data: vec![], destination: (TARGET, port).into(), source_port: 40013, scan: ScanType::IDLE, hostnames: None }
+	}
+
+	#[test]
+	fn zombie_suitability() {
+		let mut scanner = Scanner::new();
+		assert!(incremental(&[65534, 65535, 0, 1]));
+		assert!(!incremental(&[0, 0, 0, 0]));
+		assert!(!incremental(&[1200, 48211, 903, 30001]));
+		assert!(!incremental(&[1]));
+
+		let mut quiet = Lab { ip_id: 7, step: 1, open: vec![], answers: VecDeque::new() };
+		assert!(zombie().check(&mut quiet, &mut scanner).is_ok());
+		let mut random = Lab { ip_id: 7, step: 20000, open: vec![], answers: VecDeque::new() };
+		assert!(zombie().check(&mut random, &mut scanner).is_err());
+		let mut zeroes = Lab { ip_id: 0, step: 0, open: vec![], answers: VecDeque::new() };
+		assert!(zombie().check(&mut zeroes, &mut scanner).is_err());
+	}
+
+	#[test]
+	fn idle_scan() {
+		let mut scanner = Scanner::new();
+		let mut lab = Lab { ip_id: 65534, step: 1, open: vec![22], answers: VecDeque::new() };
+
+		let open = zombie().scan(&mut lab, &probe(22), &mut scanner).unwrap().unwrap();
+		assert_eq!(open.origin, SocketAddr::from((TARGET, 22)));
+		assert_eq!(open.probe_id, 40013);
+		assert!(matches!(open.kind, ResponseKind::IpId(2)));
+		assert!(matches!(zombie().scan(&mut lab, &probe(23), &mut scanner).unwrap().unwrap().kind, ResponseKind::IpId(1)));
+
+		// Busy zombies tell nothing
+		let mut busy = Lab { ip_id: 0, step: 5, open: vec![], answers: VecDeque::new() };
+		assert!(zombie().scan(&mut busy, &probe(22), &mut scanner).unwrap().is_none());
+	}
+
+	#[test]
+	fn zombie_addresses() {
+		assert_eq!(parse_zombie("10.0.0.9"), Ok(SocketAddrV4::new(ZOMBIE, 80)));
+		assert_eq!(parse_zombie(" 10.0.0.9:443 "), Ok(SocketAddrV4::new(ZOMBIE, 443)));
+		assert!(parse_zombie("10.0.0.9:http").is_err());
+		assert!(parse_zombie("::1").is_err());
+	}
+}
@@ -0,0 +1,237 @@
+use std::net::IpAddr;
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+
+use crate::iterators::Protocol;
+
+/*
+** Targets read from the output of a previous scan
+** hosts with open ports are only probed on those ports
+** live hosts without any open port are scanned like regular targets
+*/
+#[derive(Default, Debug, PartialEq)]
+pub struct Imported {
+	pub hosts: Vec<IpAddr>,
+	pub endpoints: Vec<(IpAddr, Protocol, u16)>
+}
+
+// Format is guessed from the first character:
+// nmap XML, masscan JSON or this tool's JSON lines, masscan list otherwise
+pub fn read(path: &str) -> Result<Imported> {
+	let content = std::fs::read_to_string(path.trim()).map_err(|e| anyhow!("{path}: {e}"))?;
+	parse(&content).map_err(|e| anyhow!("{path}: {e}"))
+}
+
+pub fn parse(content: &str) -> Result<Imported> {
+	let mut imported = match content.trim_start().chars().next() {
+		Some('<') => parse_nmap_xml(content)?,
+		Some('[') | Some('{') => parse_json(content)?,
+		_ => parse_list(content)?
+	};
+
+	imported.endpoints.sort();
+	imported.endpoints.dedup();
+	imported.hosts.sort();
+	imported.hosts.dedup();
+	// endpoints are sorted by address first
+	imported.hosts.retain(|host| imported.endpoints.binary_search_by_key(host, |(addr, _, _)| *addr).is_err());
+	Ok(imported)
+}
+
+// Value of attribute name in a tag like `port protocol="tcp" portid="22"`
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+	let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+	let end = tag[start..].find('"')? + start;
+	Some(&tag[start..end])
+}
+
+fn parse_nmap_xml(content: &str) -> Result<Imported> {
+	let mut imported = Imported::default();
+	let mut host: Option<IpAddr> = None;
+	let mut up = true;
+	let mut open = false;
+	let mut port: Option<(Protocol, u16)> = None;
+
+	for tag in content.split('<').skip(1).filter_map(|t| t.split('>').next()) {
+		let name = tag.split_whitespace().next().unwrap_or("");
+		match name {
+			"host" => {
+				(host, up, open) = (None, true, false);
+			},
+			"status" => up = attribute(tag, "state") != Some("down"),
+			"address" if matches!(attribute(tag, "addrtype"), Some("ipv4" | "ipv6")) => {
+				host = attribute(tag, "addr").and_then(|a| a.parse().ok());
+			},
+			"port" => {
+				let protocol = attribute(tag, "protocol").and_then(|p| Protocol::try_from(p).ok());
+				let portid = attribute(tag, "portid").and_then(|p| p.parse::<u16>().ok());
+				port = protocol.zip(portid);
+			},
+			"state" => {
+				if let (Some(addr), Some((protocol, p)), Some("open")) = (host, port, attribute(tag, "state")) {
+					imported.endpoints.push((addr, protocol, p));
+					open = true;
+				}
+			},
+			"/port" => port = None,
+			"/host" => {
+				if let (Some(addr), true, false) = (host, up, open) {
+					imported.hosts.push(addr);
+				}
+				host = None;
+			},
+			_ => ()
+		}
+	}
+
+	if !content.contains("<nmaprun") {
+		return Err(anyhow!("not an nmap XML output"));
+	}
+	Ok(imported)
+}
+
+// masscan writes one object per line, sometimes with a trailing comma after the last one
+// this tool writes one object per port
+fn parse_json(content: &str) -> Result<Imported> {
+	let mut imported = Imported::default();
+
+	for (n, line) in content.lines().enumerate() {
+		let line = line.trim().trim_end_matches(',');
+		if line.is_empty() || line == "[" || line == "]" {
+			continue ;
+		}
+
+		let object: Value = serde_json::from_str(line).map_err(|e| anyhow!("line {}: {e}", n + 1))?;
+		let addr = object["ip"].as_str()
+			.and_then(|ip| ip.parse::<IpAddr>().ok())
+			.ok_or(anyhow!("line {}: missing or invalid \"ip\"", n + 1))?;
+
+		let ports = match object["ports"].as_array() {
+			Some(ports) => ports.iter().collect(),
+			None => vec![&object]
+		};
+		for port in ports.into_iter() {
+			let protocol = port["proto"].as_str().and_then(|p| Protocol::try_from(p).ok());
+			let number = port["port"].as_u64().and_then(|p| u16::try_from(p).ok());
+
+			if let (Some(protocol), Some(number), Some("open")) = (protocol, number, port["status"].as_str()) {
+				imported.endpoints.push((addr, protocol, number));
+			}
+		}
+	}
+
+	Ok(imported)
+}
+
+// masscan list output, "open tcp 80 10.0.0.1 1390000000"
+fn parse_list(content: &str) -> Result<Imported> {
+	let mut imported = Imported::default();
+
+	for (n, line) in content.lines().enumerate() {
+		let fields: Vec<&str> = line.split_whitespace().collect();
+		match fields.first() {
+			None => continue,
+			Some(f) if f.starts_with('#') => continue,
+			Some(&"open") if fields.len() >= 4 => {
+				let protocol = Protocol::try_from(fields[1]);
+				let port = fields[2].parse::<u16>();
+				let addr = fields[3].parse::<IpAddr>();
+
+				match (protocol, port, addr) {
+					(Ok(protocol), Ok(port), Ok(addr)) => imported.endpoints.push((addr, protocol, port)),
+					_ => return Err(anyhow!("line {}: \"{}\" is not a valid masscan entry", n + 1, line.trim()))
+				}
+			},
+			Some(&"open") => return Err(anyhow!("line {}: \"{}\" is not a valid masscan entry", n + 1, line.trim())),
+			_ => continue // closed ports, banners...
+		}
+	}
+
+	Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr};
+	use super::{parse, Imported};
+	use crate::iterators::Protocol;
+
+	fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+		Ipv4Addr::new(a, b, c, d).into()
+	}
+
+	#[test]
+	fn import_nmap_xml() {
+		const CONTENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nmaprun scanner="nmap" args="nmap -sS 10.0.0.0/30">
+<host starttime="1" endtime="2"><status state="up" reason="echo-reply"/>
+<address addr="10.0.0.1" addrtype="ipv4"/><address addr="00:11:22:33:44:55" addrtype="mac"/>
+<ports><port protocol="tcp" portid="22"><state state="open" reason="syn-ack"/><service name="ssh"/></port>
+<port protocol="tcp" portid="25"><state state="closed" reason="reset"/></port>
+<port protocol="udp" portid="53"><state state="open" reason="udp-response"/></port>
+</ports></host>
+<host><status state="up"/><address addr="10.0.0.2" addrtype="ipv4"/>
+<ports><extraports state="closed" count="1000"/></ports></host>
+<host><status state="down"/><address addr="10.0.0.3" addrtype="ipv4"/></host>
+</nmaprun>"#;
+
+		assert_eq!(parse(CONTENT).unwrap(), Imported {
+			hosts: vec![ip(10, 0, 0, 2)],
+			endpoints: vec![
+				(ip(10, 0, 0, 1), Protocol::Tcp, 22),
+				(ip(10, 0, 0, 1), Protocol::Udp, 53)
+			]
+		});
+	}
+
+	#[test]
+	fn import_masscan_json() {
+		const CONTENT: &str = r#"[
+{   "ip": "10.0.0.7",   "timestamp": "1680000000", "ports": [ {"port": 443, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 64} ] },
+{   "ip": "10.0.0.5",   "timestamp": "1680000000", "ports": [ {"port": 80, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 64} ] },
+{   "ip": "10.0.0.5",   "timestamp": "1680000000", "ports": [ {"port": 0, "proto": "icmp", "status": "open"} ] },
+]"#;
+
+		assert_eq!(parse(CONTENT).unwrap().endpoints, vec![
+			(ip(10, 0, 0, 5), Protocol::Tcp, 80),
+			(ip(10, 0, 0, 7), Protocol::Tcp, 443)
+		]);
+	}
+
+	#[test]
+	fn import_own_output() {
+		const CONTENT: &str = "{\"ip\":\"10.0.0.1\",\"port\":22,\"proto\":\"tcp\",\"status\":\"open\"}\n\
+			{\"ip\":\"10.0.0.1\",\"port\":23,\"proto\":\"tcp\",\"status\":\"closed\"}\n";
+
+		assert_eq!(parse(CONTENT).unwrap().endpoints, vec![(ip(10, 0, 0, 1), Protocol::Tcp, 22)]);
+	}
+
+	#[test]
+	fn import_masscan_list() {
+		const CONTENT: &str = "#masscan\nopen tcp 80 10.0.0.1 1390000000\nopen udp 161 10.0.0.2 1390000000\nopen tcp 80 10.0.0.1 1390000001\n# end\n";
+
+		assert_eq!(parse(CONTENT).unwrap().endpoints, vec![
+			(ip(10, 0, 0, 1), Protocol::Tcp, 80),
+			(ip(10, 0, 0, 2), Protocol::Udp, 161)
+		]);
+	}
+
+	#[test]
+	fn import_ipv6() {
+		const CONTENT: &str = r#"<nmaprun scanner="nmap"><host><status state="up"/><address addr="2001:db8::1" addrtype="ipv6"/>
+<ports><port protocol="tcp" portid="22"><state state="open"/></port></ports></host></nmaprun>"#;
+		let addr: IpAddr = "2001:db8::1".parse().unwrap();
+
+		assert_eq!(parse(CONTENT).unwrap().endpoints, vec![(addr, Protocol::Tcp, 22)]);
+		assert_eq!(parse("{\"ip\":\"2001:db8::1\",\"port\":80,\"proto\":\"tcp\",\"status\":\"open\"}\n").unwrap().endpoints, vec![(addr, Protocol::Tcp, 80)]);
+		assert_eq!(parse("open udp 53 2001:db8::1 1390000000\n").unwrap().endpoints, vec![(addr, Protocol::Udp, 53)]);
+	}
+
+	#[test]
+	fn import_bad_format() {
+		assert!(parse("open tcp 80\n").is_err());
+		assert!(parse("open tcp http 10.0.0.1 0\n").is_err());
+		assert!(parse("{\"port\": 80}\n").is_err());
+		assert!(parse("<html></html>").is_err());
+	}
+}
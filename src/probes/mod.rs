@@ -1,176 +1,593 @@
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader};
-use std::iter::Peekable;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use pnet::packet::ip::IpNextHeaderProtocols;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, Receiver};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::ipv4::{MutableIpv4Packet, checksum};
-use pnet::packet::tcp::{MutableTcpPacket, ipv4_checksum as tcp_checksum};
-use pnet::packet::udp::{MutableUdpPacket, ipv4_checksum as udp_checksum};
+use pnet::packet::ipv6::MutableIpv6Packet;
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpFlags};
+use pnet::packet::udp::{self, MutableUdpPacket};
 use anyhow::{Result, anyhow};
 use rand::Rng;
 
+pub mod connect;
+pub mod ftp;
+pub mod idle;
+pub mod import;
 pub mod report;
+pub mod resolver;
 pub mod response;
+pub mod safety;
+pub mod sctp;
 
-use crate::{cli, SCAN_NUM};
-use crate::iterators::{LoopIterator, PortRange, ScanType};
+use crate::{cli, dns, PROTO_PORT, SCAN_NUM, STREAM_BUFFER};
+use crate::iterators::{hosts, ports, top_ports, Indexed, HostRange, LoopIterator, Permutation, PortRange, Protocol, ScanType, Shard};
+use idle::Zombie;
+use resolver::{Hostnames, Resolver};
+use safety::{Reason, Safety};
 
 #[derive(Debug)]
 pub struct ProbeBuilder {
-	hosts: Peekable<LoopIterator<Ipv4Addr>>,
-	scans: Peekable<LoopIterator<ScanType>>,
-	ports: Peekable<LoopIterator<PortRange>>,
-	source_addr: Ipv4Addr,
+	hosts: Indexed<HostRange>,
+	ports: Vec<Indexed<PortRange>>, // one per scan
+	scans: Vec<ScanType>,
+	imported: Vec<(IpAddr, Protocol, u16)>,
+	seed: Option<u64>,
+	order: Option<Permutation>,
+	shard: Shard,
+	next: u64,
+	source_addr: Option<Ipv4Addr>,
+	source_addr6: Option<Ipv6Addr>,
+	zombie: Option<SocketAddrV4>, // IDLE probes are sent from its address
 	source_port: u16,
-	tcp_seq: u32
+	tcp_seq: u32,
+	scanflags: u16, // of CUSTOM scans
+	excluded: u64,
+	skipped: [u64; 2], // IPv4 and IPv6
+	safety: Safety,
+	stream: Option<hosts::Stream>,
+	stream_host: Option<IpAddr>,
+	stream_next: u64,
+	stream_order: Option<Permutation>, // of the current streamed host
+	hostnames: HashMap<IpAddr, Arc<Vec<String>>>, // of static targets, read-only once they are resolved
+	stream_hostnames: Hostnames, // filled as streamed targets are resolved
+	stream_host_names: Option<Arc<Vec<String>>>
 }
 
 impl ProbeBuilder {
-	pub fn new(options: cli::Args, source: Ipv4Addr) -> Result<Self> {
-		let mut hosts: Vec<Ipv4Addr> = vec![];
+	// IPv4 targets are skipped without an IPv4 source address, as IPv6 ones without set_source_v6
+	pub fn new(options: cli::Args, source: Option<Ipv4Addr>) -> Result<Self> {
+		let mut hosts: Vec<HostRange> = vec![];
+		let mut targets: Vec<String> = vec![];
+		let mut unreadable = HashMap::new(); // why targets that are not hosts could not be opened either
+		let mut streamed = false;
+		let client = dns::client(&options);
+		let resolver = Resolver::new(options.resolve_all, client.clone());
+		let safety = Safety::new(options.allow_special);
 
 		for str in options.ip.into_iter() {
-			if let Ok(ipv4) = resolve_ipv4_address(&str) {
-				hosts.push(ipv4);
+			if str.trim() == "-" { // read targets from stdin as they come
+				streamed = true;
 				continue ;
 			}
-				
+
+			if hosts::is_spec(&str) && hosts::parse(&str).is_ok() {
+				targets.push(str);
+				continue ;
+			}
+
+			// Anything that is not a file is a target on its own
+			// hostnames can't contain '/' so those can only be files
 			let file = match std::fs::File::open(str.trim()) {
 				Ok(f) => f,
+				Err(_) if hosts::is_spec(&str) => {
+					targets.push(str);
+					continue ;
+				},
+				Err(e) if str.contains('/') => {
+					eprintln!("warning: {}: {e}, ignored", str.trim());
+					continue ;
+				},
 				Err(e) => {
-					eprintln!("warning: {str}: {e}");
+					unreadable.insert(targets.len(), e);
+					targets.push(str);
 					continue ;
 				}
 			};
 
 			let buffer = BufReader::new(file);
 			for line in buffer.lines() {
-				let line = match line {
-					Ok(l) => l,
-					Err(e) => {
-						eprintln!("warning: ignoring {str}: {e}");
-						continue ;
-					}
-				};
-
-				match resolve_ipv4_address(&line) {
-					Ok(ipv4) => hosts.push(ipv4),
-					Err(e) => eprintln!("warning: {e}, ignored")
+				match line {
+					Ok(l) => targets.push(l),
+					Err(e) => eprintln!("warning: ignoring {str}: {e}")
 				};
 			}
 		}
 
-		if hosts.len() == 0 {
+		// Hostnames are resolved all at once
+		let (mut edges, mut named) = (vec![], vec![]);
+		for (index, result) in resolver.resolve_many(&targets).into_iter().enumerate() {
+			match (result, unreadable.get(&index)) {
+				(Ok(ranges), _) => {
+					let target_edges = safety::cidr_edges(&targets[index]);
+					named.extend(safety::without_edges(&ranges, &target_edges));
+					edges.extend(target_edges);
+					hosts.extend(ranges);
+				},
+				(Err(e), Some(file)) => eprintln!("warning: {e}, nor can it be read as a file ({file}), ignored"),
+				(Err(e), None) => eprintln!("warning: {e}, ignored")
+			};
+		}
+
+		// Imported hosts with known open ports are only probed on those ports
+		let mut imported = vec![];
+		for path in options.import.iter() {
+			let content = import::read(path)?;
+			named.extend(content.hosts.iter().copied().map(HostRange::from));
+			hosts.extend(content.hosts.into_iter().map(HostRange::from));
+			imported.extend(content.endpoints);
+		}
+		imported.sort();
+		imported.dedup();
+
+		let excluded = hosts::merge(resolve_exclusions(&options.exclude, &options.exclude_file, client)?);
+		let hosts = hosts::merge(hosts);
+		// Imported endpoints on an excluded target address are already counted with it
+		let mut dropped: Vec<IpAddr> = imported.iter().map(|(addr, _, _)| *addr)
+			.filter(|addr| hosts::contains(&excluded, *addr) && !hosts::contains(&hosts, *addr))
+			.collect();
+		let (hosts, mut excluded_count) = hosts::subtract(hosts, &excluded);
+		dropped.dedup();
+		excluded_count += dropped.len() as u64;
+		imported.retain(|(addr, _, _)| !hosts::contains(&excluded, *addr));
+		// Special-use imported addresses are skipped and reported like the other targets
+		let allowed = safety.filter(imported.iter().map(|(addr, _, _)| HostRange::from(*addr)).collect(), vec![]);
+		imported.retain(|(addr, _, _)| hosts::contains(&allowed, *addr));
+		if let Some(excluded) = options.exclude_ports.as_ref() {
+			imported.retain(|(_, protocol, port)| !ports::contains(excluded.as_slice(), *protocol, *port));
+		}
+		let hosts = safety.filter(hosts, safety::unnamed(edges, named));
+		if hosts.is_empty() && imported.is_empty() && !streamed {
 			return Err(anyhow!("no valid target to scan"));
 		}
-		hosts.sort();
-		hosts.dedup();
+		// A seed picked at random would give each shard its own order
+		if options.shard.count > 1 && options.randomize && options.seed.is_none() {
+			return Err(anyhow!("--shard with --randomize needs an explicit --seed shared by every shard"));
+		}
+		let custom = options.scans.as_slice().contains(&ScanType::CUSTOM);
+		if custom != options.scanflags.is_some() {
+			return Err(anyhow!("CUSTOM scans and --scanflags go together"));
+		}
+		let idle = options.scans.as_slice().contains(&ScanType::IDLE);
+		if idle != options.zombie.is_some() {
+			return Err(anyhow!("IDLE scans and --zombie go together"));
+		}
+		let bounce = options.scans.as_slice().contains(&ScanType::BOUNCE);
+		if bounce != options.ftp_relay.is_some() {
+			return Err(anyhow!("BOUNCE scans and --ftp-relay go together"));
+		}
 
-		Ok(Self {
-			hosts: LoopIterator::from(hosts).peekable(),
-			ports: options.ports.peekable(),
-			scans: options.scans.peekable(),
+		let mut ports = top_ports::select(options.top_ports, options.port_ratio).unwrap_or_else(|| options.ports.clone());
+		if let Some(excluded) = options.exclude_ports.as_ref() {
+			ports = LoopIterator::from(ports::exclude(ports.as_slice(), excluded.as_slice()));
+		}
+		let mut builder = Self {
+			hosts: Indexed::from(vec![]),
+			ports: options.scans.as_slice().iter().map(|scan| scan_ports(&ports, *scan)).collect(),
+			scans: options.scans.as_slice().to_vec(),
+			imported,
+			seed: options.seed.or_else(|| options.randomize.then(rand::random)),
+			order: None,
+			shard: options.shard,
+			next: options.shard.index,
 			source_addr: source,
+			source_addr6: None,
+			zombie: options.zombie,
 			source_port: rand::thread_rng().gen_range(1025..=(u16::MAX - SCAN_NUM)),
-			tcp_seq: rand::random()
-		})
+			tcp_seq: rand::random(),
+			scanflags: options.scanflags.unwrap_or_default(),
+			excluded: excluded_count,
+			skipped: [0; 2],
+			safety: safety.clone(),
+			stream: None,
+			stream_host: None,
+			stream_next: options.shard.index,
+			stream_order: None,
+			hostnames: resolver.hostnames().lock().unwrap().clone(),
+			stream_hostnames: resolver.hostnames(),
+			stream_host_names: None
+		};
+
+		if builder.ports_len() == 0 && builder.imported.is_empty() {
+			return Err(anyhow!("no port to scan with the selected scan types"));
+		}
+
+		if streamed {
+			let source = stream_targets(BufReader::new(std::io::stdin()), hosts, resolver, safety);
+			builder.stream = Some(hosts::Stream::new(source, excluded));
+		} else {
+			builder.hosts = Indexed::from(hosts);
+		}
+		builder.order = builder.seed.map(|seed| Permutation::new(builder.len(), seed));
+		Ok(builder)
+	}
+
+	// Streamed targets may still come after next gave None
+	pub fn is_waiting(&self) -> bool {
+		self.stream.as_ref().is_some_and(|stream| !stream.is_closed())
+	}
+
+	// Number of target addresses removed by exclusion lists
+	pub fn excluded(&self) -> u64 {
+		self.excluded + self.stream.as_ref().map_or(0, |stream| stream.excluded())
 	}
-}
 
-fn resolve_ipv4_address(addr: &str) -> Result<Ipv4Addr> {
-	if let Ok(ipv4) = addr.parse::<Ipv4Addr>() {
-		return Ok(ipv4);
+	// IPv6 targets are skipped until an address to send them from is known
+	pub fn set_source_v6(&mut self, source: Ipv6Addr) {
+		self.source_addr6 = Some(source);
 	}
 
-	if let Ok(ips) = dns_lookup::lookup_host(addr.trim()) {
-		if let Some(IpAddr::V4(ipv4)) = ips.iter().filter(|ip| ip.is_ipv4()).next() {
-			return Ok(*ipv4);
+	// Host IDLE probes are sent in the name of, with the probe that reads its IP-ID
+	// the probe comes from a port no scan uses, so that answers are not taken for scan results
+	pub fn zombie(&self) -> Option<Zombie> {
+		let (zombie, source) = (self.zombie?, self.source_addr?);
+		let flags = TcpFlags::SYN | TcpFlags::ACK; // answered with a RST whatever the port
+		let header = self.tcp_header(self.source_port + SCAN_NUM, zombie.port(), flags, source.into(), (*zombie.ip()).into());
+
+		Some(Zombie::new(Probe {
+			data: ipv4_packet(source, *zombie.ip(), IpNextHeaderProtocols::Tcp, &header),
+			destination: zombie.into(),
+			source_port: self.source_port + SCAN_NUM,
+			scan: ScanType::IDLE,
+			hostnames: None
+		}))
+	}
+
+	// Number of IPv4 and IPv6 probes that could not be built for lack of a source address
+	pub fn skipped(&self) -> [u64; 2] {
+		self.skipped
+	}
+
+	// Special-use addresses and network or broadcast addresses of CIDR blocks left out so far
+	pub fn skipped_targets(&self) -> Vec<(HostRange, Reason)> {
+		self.safety.skipped()
+	}
+
+	// Key of the random order, if any
+	pub fn seed(&self) -> Option<u64> {
+		self.seed
+	}
+
+	// Most ports any scan probes
+	fn ports_len(&self) -> u64 {
+		self.ports.iter().map(|ports| ports.len()).max().unwrap_or(0)
+	}
+
+	// Size of the probe space known in advance
+	// every host is paired with every scan and port, then imported ports with every scan
+	// some of them are skipped because scan and port protocols differ
+	fn len(&self) -> u64 {
+		let scans = self.scans.len() as u64;
+		self.hosts.len() * scans * self.ports_len() + self.imported.len() as u64 * scans
+	}
+
+	// Hosts vary first, then scans and finally ports
+	fn probe_at(&self, index: u64) -> Option<(IpAddr, ScanType, u16)> {
+		let (hosts, scans) = (self.hosts.len(), self.scans.len() as u64);
+		let grid = hosts * scans * self.ports_len();
+
+		if index < grid {
+			let scan = (index / hosts % scans) as usize;
+			return self.scan_port(scan, index / (hosts * scans)).map(|port| (self.hosts.get(index % hosts), self.scans[scan], port));
 		}
+
+		// Imported ports are only probed by scans of the same protocol
+		let index = index - grid;
+		let (host, protocol, port) = self.imported[(index / scans) as usize];
+		let scan = self.scans[(index % scans) as usize];
+		(scan.protocol() == protocol).then_some((host, scan, port))
 	}
 
-	Err(anyhow!("\"{addr}\" does not represent any valid IPv4 address"))
+	// Scans with fewer ports than others leave holes in the probe space
+	fn scan_port(&self, scan: usize, index: u64) -> Option<u16> {
+		let ports = &self.ports[scan];
+		(index < ports.len()).then(|| ports.get(index))
+	}
+
+	// Probes are numbered once and for all, the permutation only changes
+	// the order in which numbers are visited and shards split that order
+	fn next_static(&mut self) -> Option<(IpAddr, ScanType, u16)> {
+		while self.next < self.len() {
+			let index = match &self.order {
+				Some(order) => order.get(self.next),
+				None => self.next
+			};
+			self.next += self.shard.count;
+
+			if let Some(probe) = self.probe_at(index) {
+				return Some(probe);
+			}
+		}
+
+		None
+	}
+
+	// Streamed hosts can't be iterated more than once
+	// so every scan and port is sent to a host before moving to the next one
+	// when randomized, each host gets its own order
+	fn next_streamed(&mut self) -> Option<(IpAddr, ScanType, u16)> {
+		let scans = self.scans.len() as u64;
+		let cells = scans * self.ports_len();
+
+		loop {
+			if let Some(host) = self.stream_host.filter(|_| self.stream_next < cells) {
+				let index = match self.stream_order.as_ref() {
+					Some(order) => order.get(self.stream_next),
+					None => self.stream_next
+				};
+				self.stream_next += self.shard.count;
+
+				let scan = (index % scans) as usize;
+				match self.scan_port(scan, index / scans) {
+					Some(port) => return Some((host, self.scans[scan], port)),
+					None => continue
+				}
+			}
+
+			let host = self.stream.as_mut()?.next()?;
+			self.stream_host = Some(host);
+			self.stream_host_names = self.stream_hostnames.lock().unwrap().get(&host).cloned();
+			self.stream_next = self.shard.index;
+			self.stream_order = self.seed.map(|seed| Permutation::new(cells, seed ^ host_key(host)));
+		}
+	}
 }
 
-pub struct Probe {
-	pub data: [u8; 40],
-	pub destination: SocketAddr,
-	pub source_port: u16,
-	pub scan: ScanType
+// Ports given for the protocol of a scan, or for every protocol
+// IP protocol scans take protocol numbers instead, up to 255
+fn scan_ports(ports: &LoopIterator<PortRange>, scan: ScanType) -> Indexed<PortRange> {
+	let ranges = ports.as_slice().iter().filter(|range| range.applies_to(scan.protocol()));
+	Indexed::from(match scan {
+		ScanType::PROTO => ranges.filter_map(|range| range.below(u8::MAX as u16)).collect::<Vec<PortRange>>(),
+		_ => ranges.copied().collect::<Vec<PortRange>>()
+	})
 }
 
-impl Iterator for ProbeBuilder {
-	type Item = Probe;
+// Reads targets line by line in the background
+// so that the first probes are sent while the input is still being read
+fn stream_targets<R: BufRead + Send + 'static>(input: R, initial: Vec<HostRange>, resolver: Resolver, safety: Safety) -> Receiver<HostRange> {
+	let (tx, rx) = sync_channel(STREAM_BUFFER);
 
-	fn next(&mut self) -> Option<Self::Item> {
-		let scan;
-		let port;
-		let host;
-
-		if let Some(h) = self.hosts.next() {
-			host = h;
-			scan = *self.scans.peek().unwrap();
-			port = *self.ports.peek().unwrap();
-		} else {
-			host = self.hosts.next().unwrap();
-			self.scans.next();
-
-			if let Some(s) = self.scans.peek() {
-				scan = *s;
-				port = *self.ports.peek().unwrap();
-			} else {
-				self.scans.next();
-				scan = *self.scans.peek().unwrap();
-
-				self.ports.next();
-				if let Some(p) = self.ports.peek() {
-					port = *p;
-				} else {
-					return None;
+	std::thread::spawn(move || {
+		for range in initial.into_iter() {
+			if tx.send(range).is_err() {
+				return ;
+			}
+		}
+
+		for line in input.lines() {
+			let line = match line {
+				Ok(l) => l,
+				Err(e) => {
+					eprintln!("warning: stopped reading targets: {e}");
+					return ;
 				}
+			};
+			if line.trim().is_empty() {
+				continue ;
+			}
+
+			match resolver.resolve(&line) {
+				Ok(ranges) => {
+					for range in safety.filter(ranges, safety::cidr_edges(&line)).into_iter() {
+						if tx.send(range).is_err() {
+							return ; // nobody is listening anymore
+						}
+					}
+				},
+				Err(e) => eprintln!("warning: {e}, ignored")
+			};
+		}
+	});
+
+	rx
+}
+
+// Unlike targets, exclusions that cannot be resolved are fatal
+// we'd rather not scan at all than scan a host we were told to leave alone
+fn resolve_exclusions(exclude: &[String], files: &[String], client: Option<Arc<dns::Client>>) -> Result<Vec<HostRange>> {
+	let mut targets = exclude.to_vec();
+
+	for path in files.iter() {
+		let file = std::fs::File::open(path.trim()).map_err(|e| anyhow!("{path}: {e}"))?;
+
+		for line in BufReader::new(file).lines() {
+			let line = line.map_err(|e| anyhow!("{path}: {e}"))?;
+			if !line.trim().is_empty() {
+				targets.push(line);
 			}
 		}
+	}
+
+	let resolver = Resolver::new(true, client); // every address of an excluded hostname is excluded
+	let mut excluded = vec![];
+	for result in resolver.resolve_many(&targets).into_iter() {
+		excluded.extend(result?);
+	}
+
+	Ok(excluded)
+}
+
+// Folds an address into a u64, to derive per-host keys
+fn host_key(addr: IpAddr) -> u64 {
+	match addr {
+		IpAddr::V4(addr) => u32::from(addr) as u64,
+		IpAddr::V6(addr) => {
+			let value = u128::from(addr);
+			(value ^ (value >> 64)) as u64
+		}
+	}
+}
+
+impl ProbeBuilder {
+	// Smallest valid header of an IP protocol, so that hosts speaking it answer
+	// they are sent from the PROTO scan port, so that answers to TCP, UDP and SCTP are not taken for port scan results
+	fn protocol_header(&self, protocol: u8, source: IpAddr, destination: IpAddr) -> Vec<u8> {
+		let source_port = self.source_port + ScanType::PROTO as u16;
+		let mut header = match IpNextHeaderProtocol(protocol) {
+			IpNextHeaderProtocols::Tcp => return self.transport_header(ScanType::ACK, source_port, PROTO_PORT, source, destination),
+			IpNextHeaderProtocols::Udp => return self.transport_header(ScanType::UDP, source_port, PROTO_PORT, source, destination),
+			IpNextHeaderProtocols::Sctp => return self.transport_header(ScanType::INIT, source_port, PROTO_PORT, source, destination),
+			IpNextHeaderProtocols::Icmp => [[8, 0, 0, 0], [0, 0, 0, 0]].concat(), // echo request
+			IpNextHeaderProtocols::Igmp => [[0x11, 0, 0, 0], [0, 0, 0, 0]].concat(), // general membership query
+			_ => return vec![]
+		};
 
-		let packet = &mut [0u8; 40];
-		let mut ip = MutableIpv4Packet::new(packet).unwrap();
-		ip.set_version(4);
-		ip.set_source(self.source_addr);
-		ip.set_destination(host);
-		ip.set_header_length(5);
-		ip.set_ttl(64);
+		let checksum = pnet::util::checksum(&header, 1);
+		header[2..4].copy_from_slice(&checksum.to_be_bytes());
+		header
+	}
 
-		let next_protocol_header = &mut [0u8; 20];
+	// TCP or UDP header, the checksum depends on the network layer
+	// each scan sends from its own port, which tells answers apart
+	fn transport_header(&self, scan: ScanType, source_port: u16, port: u16, source: IpAddr, destination: IpAddr) -> Vec<u8> {
 		match scan {
+			ScanType::PROTO => self.protocol_header(port as u8, source, destination),
+			ScanType::INIT => sctp::packet(source_port, port, sctp::INIT, self.tcp_seq),
+			ScanType::COOKIE => sctp::packet(source_port, port, sctp::COOKIE_ECHO, self.tcp_seq),
 			ScanType::UDP => {
-				let mut udp = MutableUdpPacket::new(&mut next_protocol_header[0..8]).unwrap();
-				udp.set_source(self.source_port);
+				let mut header = vec![0u8; 8];
+				let mut udp = MutableUdpPacket::new(&mut header).unwrap();
+				udp.set_source(source_port);
 				udp.set_destination(port);
 				udp.set_length(8);
 
-				ip.set_total_length(28);
-				ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
-				udp.set_checksum(udp_checksum(&udp.to_immutable(), &self.source_addr, &host));
-				ip.set_payload(&next_protocol_header[0..8]);
+				let checksum = match (source, destination) {
+					(IpAddr::V4(src), IpAddr::V4(dst)) => udp::ipv4_checksum(&udp.to_immutable(), &src, &dst),
+					(IpAddr::V6(src), IpAddr::V6(dst)) => udp::ipv6_checksum(&udp.to_immutable(), &src, &dst),
+					_ => unreachable!("source and destination families differ")
+				};
+				udp.set_checksum(checksum);
+				header
 			},
-			_ => {
-				let mut tcp = MutableTcpPacket::new(next_protocol_header).unwrap();
-				tcp.set_source(self.source_port);
-				tcp.set_destination(port);
-				tcp.set_data_offset(5);
-				tcp.set_sequence(self.tcp_seq);
-				tcp.set_flags(u16::try_from(scan).unwrap());
-				
-				ip.set_total_length(40);
-				ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
-				tcp.set_checksum(tcp_checksum(&tcp.to_immutable(), &self.source_addr, &host));
-				ip.set_payload(next_protocol_header);
+			ScanType::CUSTOM => self.tcp_header(source_port, port, self.scanflags, source, destination),
+			_ => self.tcp_header(source_port, port, u16::try_from(scan).unwrap(), source, destination)
+		}
+	}
+
+	fn tcp_header(&self, source_port: u16, port: u16, flags: u16, source: IpAddr, destination: IpAddr) -> Vec<u8> {
+		let mut header = vec![0u8; 20];
+		let mut tcp = MutableTcpPacket::new(&mut header).unwrap();
+		tcp.set_source(source_port);
+		tcp.set_destination(port);
+		tcp.set_data_offset(5);
+		tcp.set_sequence(self.tcp_seq);
+		tcp.set_flags(flags);
+
+		let checksum = match (source, destination) {
+			(IpAddr::V4(src), IpAddr::V4(dst)) => tcp::ipv4_checksum(&tcp.to_immutable(), &src, &dst),
+			(IpAddr::V6(src), IpAddr::V6(dst)) => tcp::ipv6_checksum(&tcp.to_immutable(), &src, &dst),
+			_ => unreachable!("source and destination families differ")
+		};
+		tcp.set_checksum(checksum);
+		header
+	}
+}
+
+fn ipv4_packet(source: Ipv4Addr, destination: Ipv4Addr, protocol: IpNextHeaderProtocol, header: &[u8]) -> Vec<u8> {
+	let mut packet = vec![0u8; 20 + header.len()];
+	let mut ip = MutableIpv4Packet::new(&mut packet).unwrap();
+	ip.set_version(4);
+	ip.set_source(source);
+	ip.set_destination(destination);
+	ip.set_header_length(5);
+	ip.set_ttl(64);
+	ip.set_total_length(20 + header.len() as u16);
+	ip.set_next_level_protocol(protocol);
+	ip.set_payload(header);
+	ip.set_checksum(checksum(&ip.to_immutable()));
+	packet
+}
+
+fn next_protocol(scan: ScanType, port: u16) -> IpNextHeaderProtocol {
+	match scan.protocol() {
+		Protocol::Ip => IpNextHeaderProtocol(port as u8),
+		Protocol::Tcp => IpNextHeaderProtocols::Tcp,
+		Protocol::Udp => IpNextHeaderProtocols::Udp,
+		Protocol::Sctp => IpNextHeaderProtocols::Sctp
+	}
+}
+
+pub struct Probe {
+	pub data: Vec<u8>,
+	pub destination: SocketAddr,
+	pub source_port: u16,
+	pub scan: ScanType,
+	pub hostnames: Option<Arc<Vec<String>>>
+}
+
+impl Iterator for ProbeBuilder {
+	type Item = Probe;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let (host, scan, port) = loop {
+			let (host, scan, port) = self.next_streamed().or_else(|| self.next_static())?;
+			if scan == ScanType::PROTO && host.is_ipv6() {
+				continue ; // protocol answers are only read from ICMP, not ICMPv6
+			}
+			if scan == ScanType::IDLE && host.is_ipv6() {
+				continue ; // zombies are IPv4 hosts, IPv6 has no IP-ID outside of fragments
+			}
+			if scan == ScanType::BOUNCE && host.is_ipv6() {
+				continue ; // PORT commands only take IPv4 addresses
+			}
+			let source = match host {
+				IpAddr::V4(_) => self.source_addr.is_some(),
+				IpAddr::V6(_) => self.source_addr6.is_some()
+			};
+			if source || matches!(scan, ScanType::CONNECT | ScanType::BOUNCE) {
+				break (host, scan, port);
+			}
+			self.skipped[host.is_ipv6() as usize] += 1;
+		};
+
+		let data = match host {
+			_ if scan == ScanType::CONNECT => vec![], // the kernel does the handshake
+			_ if scan == ScanType::BOUNCE => vec![], // so does the FTP server
+			IpAddr::V4(host) => {
+				let source = match (scan, self.zombie) {
+					(ScanType::IDLE, Some(zombie)) => *zombie.ip(),
+					_ => self.source_addr.unwrap()
+				};
+				let header = self.transport_header(scan, self.source_port + scan as u16, port, source.into(), host.into());
+				ipv4_packet(source, host, next_protocol(scan, port), &header)
+			},
+			IpAddr::V6(host) => {
+				let source = self.source_addr6.unwrap();
+				let header = self.transport_header(scan, self.source_port + scan as u16, port, source.into(), host.into());
+				let mut packet = vec![0u8; 40 + header.len()];
+				let mut ip = MutableIpv6Packet::new(&mut packet).unwrap();
+				ip.set_version(6);
+				ip.set_source(source);
+				ip.set_destination(host);
+				ip.set_hop_limit(64);
+				ip.set_payload_length(header.len() as u16);
+				ip.set_next_header(next_protocol(scan, port));
+				ip.set_payload(&header);
+				packet
 			}
 		};
-		ip.set_checksum(checksum(&ip.to_immutable()));
 
 		Some(Probe {
-			data: *packet,
+			data,
 			destination: (host, port).into(),
 			source_port: self.source_port + (scan as u16),
-			scan
+			scan,
+			hostnames: match self.stream_host == Some(host) {
+				true => self.stream_host_names.clone(),
+				false => self.hostnames.get(&host).cloned()
+			}
 		})
 	}
 }
@@ -182,9 +599,10 @@ use clap::Parser;
 #[cfg(test)]
 use pnet::packet::Packet;
 #[cfg(test)]
-use pnet::packet::{ipv4::Ipv4Packet, tcp::TcpPacket, udp::UdpPacket};
+use pnet::packet::{ipv4::Ipv4Packet, ipv6::Ipv6Packet, tcp::TcpPacket, udp::UdpPacket};
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn probe_builder_hosts_iter() -> Result<(), Box<dyn std::error::Error>> {
 	const CONTENT: &str = "\t127.0.0.1\n  192.168.1.22\n\n10.13.250.188\n";
 	let tmp = assert_fs::NamedTempFile::new("ip.tmp")?;
@@ -192,7 +610,7 @@ fn probe_builder_hosts_iter() -> Result<(), Box<dyn std::error::Error>> {
 	
 	let hosts = format!("-i {}", tmp.path().to_str().unwrap());
 	let arguments = vec![clap::crate_name!(), "-i dns.google", "-s SYN", "-p80", hosts.as_str()];
-	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into())?;
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
 	let probes: Vec<_> = builder.collect();
 	
 	let google_dns = Ipv4Packet::new(&probes[0].data).unwrap().get_destination();
@@ -207,10 +625,182 @@ fn probe_builder_hosts_iter() -> Result<(), Box<dyn std::error::Error>> {
 	Ok(())
 }
 
+#[test]
+fn probe_builder_ranges_iter() -> Result<(), Box<dyn std::error::Error>> {
+	const CONTENT: &str = "10.0.0.2/31\n";
+	let tmp = assert_fs::NamedTempFile::new("ip.tmp")?;
+	tmp.write_str(CONTENT)?;
+
+	let hosts = format!("-i {}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/30", "-i 10.0.0.8/32", "-i 10.0.0.8-9", "-s SYN", "-p80", "--allow-special", hosts.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+	let destinations: Vec<_> = builder.map(|p| Ipv4Packet::new(&p.data).unwrap().get_destination()).collect();
+
+	// Overlapping blocks are only scanned once
+	assert_eq!(destinations, vec![
+		Ipv4Addr::new(10, 0, 0, 0),
+		Ipv4Addr::new(10, 0, 0, 1),
+		Ipv4Addr::new(10, 0, 0, 2),
+		Ipv4Addr::new(10, 0, 0, 3),
+		Ipv4Addr::new(10, 0, 0, 8),
+		Ipv4Addr::new(10, 0, 0, 9)
+	]);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_exclusions() -> Result<(), Box<dyn std::error::Error>> {
+	const CONTENT: &str = "10.0.0.3\n\n10.0.0.100/30\n";
+	let tmp = assert_fs::NamedTempFile::new("exclude.tmp")?;
+	tmp.write_str(CONTENT)?;
+
+	let exclude_file = format!("--exclude-file={}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "--exclude=10.0.0.0", "--exclude=10.0.0.5-6", "-s SYN", "-p80", "--allow-special", exclude_file.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+	assert_eq!(builder.excluded(), 4);
+
+	let destinations: Vec<_> = builder.map(|p| Ipv4Packet::new(&p.data).unwrap().get_destination()).collect();
+	assert_eq!(destinations, vec![
+		Ipv4Addr::new(10, 0, 0, 1),
+		Ipv4Addr::new(10, 0, 0, 2),
+		Ipv4Addr::new(10, 0, 0, 4),
+		Ipv4Addr::new(10, 0, 0, 7)
+	]);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_exclusion_errors() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "--exclude-file=non_existing_file.txt"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())).is_err());
+
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "--exclude=10.0.0.0/33"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())).is_err());
+
+	// Nothing left to scan
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "--exclude=10.0.0.0/24"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())).is_err());
+	Ok(())
+}
+
+#[test]
+fn probe_builder_streamed_iter() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p80,443", "-s SYN,UDP", "--exclude=10.0.0.3"];
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+
+	let (tx, rx) = std::sync::mpsc::channel();
+	builder.hosts = Indexed::from(vec![]);
+	builder.stream = Some(hosts::Stream::new(rx, vec![HostRange::from(Ipv4Addr::new(10, 0, 0, 3))]));
+
+	// The first host is fully probed before the second one is known
+	tx.send(HostRange::from(Ipv4Addr::new(10, 0, 0, 1)))?;
+	let probes: Vec<_> = builder.by_ref().take(4).collect();
+	let expected = [(ScanType::SYN, 80), (ScanType::UDP, 80), (ScanType::SYN, 443), (ScanType::UDP, 443)];
+	for (probe, (scan, port)) in probes.iter().zip(expected) {
+		assert_eq!(probe.destination, SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), port)));
+		assert_eq!(probe.scan, scan);
+	}
+
+	// Nothing more to send for now, without ending the scan
+	assert!(builder.next().is_none());
+	assert!(builder.is_waiting());
+
+	// Names of streamed hosts are resolved after the builder was made
+	builder.stream_hostnames.lock().unwrap().insert(Ipv4Addr::new(10, 0, 0, 2).into(), Arc::new(vec![String::from("db.internal")]));
+	tx.send(HostRange::try_from("10.0.0.0/30")?)?;
+	drop(tx);
+	let probes: Vec<_> = builder.by_ref().collect();
+	assert!(probes[4..].iter().all(|p| p.hostnames.as_deref() == Some(&vec![String::from("db.internal")])));
+	let destinations: Vec<_> = probes.iter().map(|p| p.destination.ip()).collect();
+	assert!(!builder.is_waiting());
+	assert_eq!(destinations.len(), 8);
+	assert!(destinations[..4].iter().all(|ip| *ip == Ipv4Addr::new(10, 0, 0, 0)));
+	assert!(destinations[4..].iter().all(|ip| *ip == Ipv4Addr::new(10, 0, 0, 2)));
+	assert_eq!(builder.excluded(), 1);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_stream_reader() -> Result<(), Box<dyn std::error::Error>> {
+	let input = std::io::Cursor::new("10.0.0.1\n\n  10.0.1.0/31 \n10.0.0.300\n224.0.0.1\n10.0.2.0/30\n");
+	let source = stream_targets(input, vec![HostRange::from(Ipv4Addr::LOCALHOST)], Resolver::new(false, None), Safety::new(false));
+
+	assert_eq!(source.iter().collect::<Vec<_>>(), vec![
+		HostRange::from(Ipv4Addr::LOCALHOST),
+		HostRange::from(Ipv4Addr::new(10, 0, 0, 1)),
+		HostRange::try_from("10.0.1.0/31")?,
+		HostRange::new(Ipv4Addr::new(10, 0, 2, 1), Ipv4Addr::new(10, 0, 2, 2))
+	]);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_hostnames() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i localhost", "-i 127.0.0.1", "-i 10.0.0.1", "-s SYN", "-p80"];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+	let probes: Vec<_> = builder.collect();
+
+	assert_eq!(probes.len(), 2);
+	assert_eq!(probes[0].destination.ip(), Ipv4Addr::new(10, 0, 0, 1));
+	assert!(probes[0].hostnames.is_none());
+	assert_eq!(probes[1].destination.ip(), Ipv4Addr::LOCALHOST);
+	assert_eq!(probes[1].hostnames.as_deref(), Some(&vec![String::from("localhost")]));
+	Ok(())
+}
+
+#[test]
+fn probe_builder_imported() -> Result<(), Box<dyn std::error::Error>> {
+	const CONTENT: &str = "open tcp 22 10.0.0.1 0\nopen udp 53 10.0.0.1 0\nopen tcp 80 10.0.0.9 0\nopen tcp 443 10.0.0.2 0\n";
+	let tmp = assert_fs::NamedTempFile::new("masscan.tmp")?;
+	tmp.write_str(CONTENT)?;
+
+	let import = format!("--import={}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.5", "-p1", "-s SYN,ACK,UDP", "--exclude=10.0.0.9", import.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+	assert_eq!(builder.excluded(), 1);
+
+	let probes: Vec<_> = builder.map(|p| (p.destination, p.scan)).collect();
+	let endpoint = |d: u8, port: u16| SocketAddr::from((Ipv4Addr::new(10, 0, 0, d), port));
+	assert_eq!(probes, vec![
+		(endpoint(5, 1), ScanType::SYN),
+		(endpoint(5, 1), ScanType::ACK),
+		(endpoint(5, 1), ScanType::UDP),
+		(endpoint(1, 22), ScanType::SYN),
+		(endpoint(1, 22), ScanType::ACK),
+		(endpoint(1, 53), ScanType::UDP),
+		(endpoint(2, 443), ScanType::SYN),
+		(endpoint(2, 443), ScanType::ACK)
+	]);
+
+	// An address both given as a target and imported is excluded once
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1-2", "-p1", "-s SYN", "--exclude=10.0.0.1", import.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+	assert_eq!(builder.excluded(), 1);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_import_only() -> Result<(), Box<dyn std::error::Error>> {
+	const CONTENT: &str = "{\"ip\":\"10.0.0.1\",\"port\":22,\"proto\":\"tcp\",\"status\":\"open\"}\n";
+	let tmp = assert_fs::NamedTempFile::new("scan.json")?;
+	tmp.write_str(CONTENT)?;
+
+	let import = format!("--import={}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-s SYN", import.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
+
+	let probes: Vec<_> = builder.map(|p| p.destination).collect();
+	assert_eq!(probes, vec![SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 22))]);
+
+	let arguments = vec![clap::crate_name!(), "--import=non_existing_file.xml"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())).is_err());
+	Ok(())
+}
+
 #[test]
 fn probe_builder_ports_iter() -> Result<(), Box<dyn std::error::Error>> {
 	let arguments = vec![clap::crate_name!(), "-i dns.google", "-s SYN", "-p80,443,1024-1026"];
-	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into())?;
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
 	let probes: Vec<_> = builder.collect();
 	
 	assert_eq!(TcpPacket::new(Ipv4Packet::new(&probes[0].data).unwrap().payload()).unwrap().get_destination(), 80);
@@ -224,7 +814,7 @@ fn probe_builder_ports_iter() -> Result<(), Box<dyn std::error::Error>> {
 #[test]
 fn probe_builder_scans_iter() -> Result<(), Box<dyn std::error::Error>> {
 	let arguments = vec![clap::crate_name!(), "-i 127.0.0.1", "-p80"];
-	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into())?;
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
 	let probes: Vec<_> = builder.collect();
 	
 	assert_eq!(TcpPacket::new(Ipv4Packet::new(&probes[0].data).unwrap().payload()).unwrap().get_flags(), u16::try_from(ScanType::SYN).unwrap());
@@ -240,7 +830,7 @@ fn probe_builder_scans_iter() -> Result<(), Box<dyn std::error::Error>> {
 #[test]
 fn probe_builder_complex_iter() -> Result<(), Box<dyn std::error::Error>> {
 	let arguments = vec![clap::crate_name!(), "-i 127.0.0.1", "-i 192.168.1.157", "-p80,443", "-s SYN,UDP"];
-	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into())?;
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into()))?;
 	let probes: Vec<_> = builder.collect();
 	
 	let ip = Ipv4Packet::new(&probes[0].data).unwrap();
@@ -275,9 +865,299 @@ fn probe_builder_complex_iter() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
+fn probe_builder_randomized_iter() -> Result<(), Box<dyn std::error::Error>> {
+	let spec = |seed: Option<&'static str>| {
+		let mut arguments = vec![clap::crate_name!(), "-i 10.0.0.0/28", "-i 10.0.2.1", "-p22,80-83", "-s SYN,UDP"];
+		arguments.extend(seed);
+		arguments
+	};
+	let probes = |seed| -> Result<Vec<(SocketAddr, ScanType)>, Box<dyn std::error::Error>> {
+		let builder = ProbeBuilder::new(cli::Args::try_parse_from(spec(seed))?, Some([127, 0, 0, 1].into()))?;
+		Ok(builder.map(|p| (p.destination, p.scan)).collect())
+	};
+
+	let sequential = probes(None)?;
+	let first = probes(Some("--seed=1"))?;
+	assert_eq!(first, probes(Some("--seed=1"))?);
+	assert_ne!(first, probes(Some("--seed=2"))?);
+	assert_ne!(first, sequential);
+
+	// Same probes, only the order changes
+	let (mut sorted, mut expected) = (first.clone(), sequential.clone());
+	sorted.sort_by_key(|(addr, scan)| (*addr, *scan as u8));
+	expected.sort_by_key(|(addr, scan)| (*addr, *scan as u8));
+	assert_eq!(sequential.len(), 15 * 5 * 2); // network and broadcast addresses are skipped
+	assert_eq!(sorted, expected);
+
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(spec(Some("--randomize")))?, Some([127, 0, 0, 1].into()))?;
+	assert!(builder.seed().is_some());
+	Ok(())
+}
+
+#[test]
+fn probe_builder_sharded_iter() -> Result<(), Box<dyn std::error::Error>> {
+	let probes = |shard: String, seed: Option<&'static str>| -> Result<Vec<(SocketAddr, ScanType)>, Box<dyn std::error::Error>> {
+		let mut arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "-i 10.0.1.1", "-p22,80-82", "-s SYN,ACK,UDP", shard.as_str()];
+		arguments.extend(seed);
+		let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?;
+		Ok(builder.map(|p| (p.destination, p.scan)).collect())
+	};
+
+	for seed in [None, Some("--seed=7")] {
+		let mut expected = probes(String::from("--shard=1/1"), seed)?;
+		let mut shards = vec![];
+		for n in 1..=3 {
+			let shard = probes(format!("--shard={n}/3"), seed)?;
+			assert!(shard.len() >= expected.len() / 3);
+			shards.extend(shard);
+		}
+
+		// Disjoint and complete
+		expected.sort_by_key(|(addr, scan)| (*addr, *scan as u8));
+		shards.sort_by_key(|(addr, scan)| (*addr, *scan as u8));
+		assert_eq!(shards, expected);
+	}
+
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/29", "--randomize", "--shard=1/2"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into())).is_err());
+	Ok(())
+}
+
+#[test]
+fn probe_builder_qualified_ports() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1/31", "--allow-special", "-p T:20-22,U:53,161,S:9", "-s SYN,UDP,ACK"];
+	let probes: Vec<(u16, ScanType)> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?
+		.map(|p| (p.destination.port(), p.scan))
+		.collect();
+
+	assert_eq!(probes.len(), 2 * (3 + 2 + 3));
+	for (port, scan) in probes.iter() {
+		assert_eq!([20, 21, 22].contains(port), scan.protocol() == Protocol::Tcp);
+		assert_eq!([53, 161].contains(port), *scan == ScanType::UDP);
+	}
+
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p U:53", "-s SYN"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into())).is_err());
+	Ok(())
+}
+
+#[test]
+fn probe_builder_excluded_ports() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p 9000-9200", "-s SYN,UDP", "--exclude-ports=9100,U:9001-9200"];
+	let probes: Vec<(u16, ScanType)> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?
+		.map(|p| (p.destination.port(), p.scan))
+		.collect();
+
+	assert_eq!(probes.len(), 200 + 1);
+	assert!(probes.iter().all(|(port, scan)| *port != 9100 && (*scan == ScanType::SYN || *port == 9000)));
+
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p 22", "--exclude-ports=ssh"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into())).is_err());
+
+	// Imported open ports are left out too
+	let tmp = assert_fs::NamedTempFile::new("masscan.tmp")?;
+	tmp.write_str("open tcp 22 10.0.0.1 0\nopen udp 53 10.0.0.1 0\nopen tcp 53 10.0.0.1 0\nopen tcp 9100 10.0.0.2 0\n")?;
+	let import = format!("--import={}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-s SYN,UDP", "--exclude-ports=9100,U:53", import.as_str()];
+	let probes: Vec<(SocketAddr, ScanType)> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?
+		.map(|p| (p.destination, p.scan))
+		.collect();
+	assert_eq!(probes, vec![(SocketAddr::from(([10, 0, 0, 1], 22)), ScanType::SYN), (SocketAddr::from(([10, 0, 0, 1], 53)), ScanType::SYN)]);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_connect() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-i 2001:db8::1", "-p 22,80", "-s connect"];
+	let probes: Vec<Probe> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?.collect();
+
+	// Connect scans don't need an IPv6 source, the kernel picks it
+	assert_eq!(probes.len(), 4);
+	assert!(probes.iter().all(|p| p.data.is_empty() && p.scan == ScanType::CONNECT));
+	assert_eq!(probes[1].destination, "[2001:db8::1]:22".parse::<SocketAddr>()?);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_custom_flags() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p 80", "-s CUSTOM,SYN", "--scanflags=SYNECE"];
+	let probes: Vec<Probe> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?.collect();
+	let flags: Vec<u16> = probes.iter().map(|p| TcpPacket::new(&p.data[20..]).unwrap().get_flags()).collect();
+	assert_eq!(flags, vec![0x02, 0x42]);
+
+	for arguments in [vec![clap::crate_name!(), "-i 10.0.0.1", "-s CUSTOM"], vec![clap::crate_name!(), "-i 10.0.0.1", "--scanflags=FIN"]] {
+		assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into())).is_err());
+	}
+	Ok(())
+}
+
+#[test]
+fn probe_builder_sctp() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-p T:22,S:3868", "-s SYN,INIT,COOKIE"];
+	let probes: Vec<Probe> = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?.collect();
+	assert_eq!(probes.len(), 3);
+
+	for (probe, chunk) in probes[1..].iter().zip([sctp::INIT, sctp::COOKIE_ECHO]) {
+		let ip = Ipv4Packet::new(&probe.data).unwrap();
+		assert_eq!(ip.get_next_level_protocol(), IpNextHeaderProtocols::Sctp);
+		assert_eq!(sctp::parse(ip.payload()).map(|(_, port, c)| (port, c)), Some((3868, chunk)));
+		assert_eq!(sctp::parse(ip.payload()).map(|(source, _, _)| source), Some(probe.source_port)); // how answers find their scan
+
+		let mut zeroed = ip.payload().to_vec();
+		zeroed[8..12].copy_from_slice(&[0; 4]);
+		assert_eq!(&ip.payload()[8..12], &sctp::crc32c(&zeroed).to_le_bytes());
+	}
+	Ok(())
+}
+
+#[test]
+fn probe_builder_idle() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.2", "-i 2001:db8::1", "-p 22", "-s SYN,IDLE", "--zombie=10.0.0.9:443"];
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([10, 0, 0, 1].into()))?;
+	builder.set_source_v6("2001:db8::2".parse()?);
+
+	// The probe reading the zombie comes from us, from a port no scan uses
+	let zombie = builder.zombie().unwrap();
+	assert_eq!(zombie.address(), "10.0.0.9:443".parse::<SocketAddr>()?);
+
+	// IDLE probes are IPv4 SYNs sent in the zombie's name
+	let probes: Vec<Probe> = builder.filter(|p| p.scan == ScanType::IDLE).collect();
+	assert_eq!(probes.len(), 1);
+	let ip = Ipv4Packet::new(&probes[0].data).unwrap();
+	assert_eq!(ip.get_source(), Ipv4Addr::new(10, 0, 0, 9));
+	assert_eq!(ip.get_destination(), Ipv4Addr::new(10, 0, 0, 2));
+	let tcp = TcpPacket::new(ip.payload()).unwrap();
+	assert_eq!(tcp.get_flags(), TcpFlags::SYN);
+	assert_eq!(tcp.get_checksum(), tcp::ipv4_checksum(&tcp, &ip.get_source(), &ip.get_destination()));
+
+	assert!(cli::Args::try_parse_from(vec![clap::crate_name!(), "-i 10.0.0.2", "-s IDLE"]).map(|args| ProbeBuilder::new(args, Some([10, 0, 0, 1].into())).is_err())?);
+	assert!(cli::Args::try_parse_from(vec![clap::crate_name!(), "-i 10.0.0.2", "--zombie=10.0.0.9"]).map(|args| ProbeBuilder::new(args, Some([10, 0, 0, 1].into())).is_err())?);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_bounce() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.2", "-i 2001:db8::1", "-p 21,U:53", "-s BOUNCE,UDP", "--ftp-relay=bob:secret@10.0.0.9"];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([10, 0, 0, 1].into()))?;
+
+	// The FTP server connects to IPv4 targets for us
+	let probes: Vec<Probe> = builder.filter(|p| p.scan == ScanType::BOUNCE).collect();
+	assert_eq!(probes.len(), 1);
+	assert_eq!(probes[0].destination, "10.0.0.2:21".parse::<SocketAddr>()?);
+	assert!(probes[0].data.is_empty());
+
+	assert!(cli::Args::try_parse_from(vec![clap::crate_name!(), "-i 10.0.0.2", "-s BOUNCE"]).map(|args| ProbeBuilder::new(args, Some([10, 0, 0, 1].into())).is_err())?);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_protocols() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-i 2001:db8::1", "-p T:80,P:1-2,gre,6,250-", "-s SYN,PROTO"];
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?;
+	builder.set_source_v6("2001:db8::2".parse()?);
+	let probes: Vec<Probe> = builder.filter(|p| p.scan == ScanType::PROTO).collect();
+
+	// IPv4 only, up to protocol 255
+	let protocols: Vec<u16> = probes.iter().map(|p| p.destination.port()).collect();
+	assert_eq!(protocols, [1, 2, 6, 47, 250, 251, 252, 253, 254, 255]);
+	for probe in probes.iter() {
+		let ip = Ipv4Packet::new(&probe.data).unwrap();
+		assert_eq!(ip.get_next_level_protocol().0 as u16, probe.destination.port());
+	}
+
+	let icmp = Ipv4Packet::new(&probes[0].data).unwrap();
+	assert_eq!(icmp.payload()[0], 8);
+	assert_eq!(pnet::util::checksum(icmp.payload(), 1), u16::from_be_bytes([icmp.payload()[2], icmp.payload()[3]]));
+	assert_eq!(TcpPacket::new(Ipv4Packet::new(&probes[2].data).unwrap().payload()).unwrap().get_destination(), PROTO_PORT);
+	assert!(Ipv4Packet::new(&probes[3].data).unwrap().payload().is_empty());
+	Ok(())
+}
+
+#[test]
+fn probe_builder_ipv6() -> Result<(), Box<dyn std::error::Error>> {
+	let source: Ipv6Addr = "2001:db8::1".parse()?;
+	let target: Ipv6Addr = "2001:db8::2:0".parse()?;
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.1", "-i 2001:db8::2:0/127", "-p80", "-s SYN,UDP"];
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments.clone())?, Some([127, 0, 0, 1].into()))?;
+	builder.set_source_v6(source);
+	let probes: Vec<_> = builder.collect();
+	assert_eq!(probes.len(), 6);
+
+	// IPv4 hosts come first, then IPv6 ones
+	assert_eq!(probes[0].data.len(), 40);
+	assert_eq!(probes[1].destination, SocketAddr::from((target, 80)));
+	let ip = Ipv6Packet::new(&probes[1].data).unwrap();
+	assert_eq!((ip.get_source(), ip.get_destination()), (source, target));
+	assert_eq!(ip.get_payload_length(), 20);
+	let tcp = TcpPacket::new(ip.payload()).unwrap();
+	assert_eq!(tcp.get_flags(), u16::try_from(ScanType::SYN).unwrap());
+	assert_eq!(tcp.get_checksum(), tcp::ipv6_checksum(&tcp, &source, &target));
+
+	let ip = Ipv6Packet::new(&probes[4].data).unwrap();
+	assert_eq!(ip.get_next_header(), IpNextHeaderProtocols::Udp);
+	let udp = UdpPacket::new(ip.payload()).unwrap();
+	assert_eq!((udp.get_destination(), udp.get_length()), (80, 8));
+	assert_eq!(udp.get_checksum(), udp::ipv6_checksum(&udp, &source, &target));
+
+	// Without an IPv6 source address, IPv6 targets are skipped
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments.clone())?, Some([127, 0, 0, 1].into()))?;
+	assert_eq!(builder.by_ref().count(), 2);
+	assert_eq!(builder.skipped(), [0, 4]);
+
+	// and IPv4 targets on hosts with IPv6 only
+	let mut builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, None)?;
+	builder.set_source_v6(source);
+	assert!(builder.by_ref().all(|probe| probe.destination.is_ipv6()));
+	assert_eq!(builder.skipped(), [2, 0]);
+	Ok(())
+}
+
+#[test]
+fn probe_builder_special_addresses() -> Result<(), Box<dyn std::error::Error>> {
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/30", "-i 224.0.0.251", "-i 0.0.0.0", "-i 10.0.1.0/31", "-s SYN", "-p80"];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments.clone())?, Some([127, 0, 0, 1].into()))?;
+	let skipped: Vec<String> = builder.skipped_targets().iter().map(|(range, reason)| format!("{range} {reason}")).collect();
+	assert_eq!(skipped, ["0.0.0.0 \"this\" network", "10.0.0.0 network address", "10.0.0.3 broadcast address", "224.0.0.251 multicast"]);
+
+	let destinations: Vec<_> = builder.map(|p| p.destination.ip()).collect();
+	assert_eq!(destinations, ["10.0.0.1", "10.0.0.2", "10.0.1.0", "10.0.1.1"].map(|ip| ip.parse::<IpAddr>().unwrap()));
+
+	let mut arguments = arguments;
+	arguments.push("--allow-special");
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?;
+	assert!(builder.skipped_targets().is_empty());
+	assert_eq!(builder.count(), 8);
+
+	// Edges of a block are scanned when another target names them
+	let arguments = vec![clap::crate_name!(), "-i 10.0.0.0/30", "-i 10.0.0.3", "-s SYN", "-p80"];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?;
+	let skipped: Vec<String> = builder.skipped_targets().iter().map(|(range, reason)| format!("{range} {reason}")).collect();
+	assert_eq!(skipped, ["10.0.0.0 network address"]);
+	assert_eq!(builder.count(), 3);
+
+	// Nothing left once special addresses are skipped
+	let arguments = vec![clap::crate_name!(), "-i 255.255.255.255"];
+	assert!(ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into())).is_err());
+
+	// Imported endpoints too
+	let tmp = assert_fs::NamedTempFile::new("masscan.tmp")?;
+	tmp.write_str("open tcp 5353 224.0.0.251 0\nopen tcp 22 10.0.0.1 0\nopen udp 53 255.255.255.255 0\n")?;
+	let import = format!("--import={}", tmp.path().to_str().unwrap());
+	let arguments = vec![clap::crate_name!(), "-s SYN,UDP", import.as_str()];
+	let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments)?, Some([127, 0, 0, 1].into()))?;
+	let skipped: Vec<String> = builder.skipped_targets().iter().map(|(range, reason)| format!("{range} {reason}")).collect();
+	assert_eq!(skipped, ["224.0.0.251 multicast", "255.255.255.255 limited broadcast"]);
+
+	let destinations: Vec<_> = builder.map(|p| p.destination).collect();
+	assert_eq!(destinations, [SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 22))]);
+	Ok(())
+}
+
+#[test]
+#[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
 fn probe_builder_file_error() -> Result<(), Box<dyn std::error::Error>> {
 	let arguments = vec![clap::crate_name!(), "-i non_existing_file.txt"];
-	if let Ok(_) = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into()) {
+	if let Ok(_) = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())) {
 		assert!(false, "must complain about file existence");
 	}
 	
@@ -285,6 +1165,7 @@ fn probe_builder_file_error() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 #[test]
+#[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
 fn probe_builder_no_ip() -> Result<(), Box<dyn std::error::Error>> {
 	const CONTENT: &str = "\t127.0.ff.1\n non.existing.domain\n\nHello, World!\n";
 	let tmp = assert_fs::NamedTempFile::new("ip.tmp")?;
@@ -293,7 +1174,7 @@ fn probe_builder_no_ip() -> Result<(), Box<dyn std::error::Error>> {
 	let hosts = format!("-i {}", tmp.path().to_str().unwrap());
 	let arguments = vec![clap::crate_name!(), "-i foobar", hosts.as_str()];
 	
-	if let Ok(_) = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), [127, 0, 0, 1].into()) {
+	if let Ok(_) = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([127, 0, 0, 1].into())) {
 		assert!(false, "must complain about not having addresses to scan");
 	}
 	
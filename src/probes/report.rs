@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
+use std::io::Write;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Instant;
 
 use pnet::packet::icmp::destination_unreachable::IcmpCodes;
@@ -9,10 +11,16 @@ use pnet::packet::tcp::TcpFlags;
 
 use num_enum::IntoPrimitive;
 
-use crate::iterators::ScanType;
+use anyhow::Result;
+use serde_json::json;
+
+use crate::iterators::{services, Protocol, ScanType};
+use crate::iterators::scans::{Reset, Silence};
 use super::Probe;
-use super::response::{Response, ResponseKind};
-use crate::{ACCEPTED_ICMP_CODES, DEFAULT_TIMEOUT};
+use super::resolver::ReverseResolver;
+use super::sctp;
+use super::response::{self, Response, ResponseKind};
+use crate::{dns, ACCEPTED_ICMP_CODES, DEFAULT_TIMEOUT};
 
 #[derive(IntoPrimitive, PartialEq, PartialOrd)]
 #[repr(u8)]
@@ -20,6 +28,7 @@ enum PortStatus {
 	Filtered,
 	Unfiltered,
 	OpenOrFiltered,
+	ClosedOrFiltered,
 	Closed,
 	Open
 }
@@ -29,12 +38,12 @@ impl TryFrom<(ResponseKind, ScanType)> for PortStatus {
 	
 	fn try_from(value: (ResponseKind, ScanType)) -> Result<Self, Self::Error> {
 		match value.1 {
-			ScanType::SYN => {
+			ScanType::SYN | ScanType::CONNECT => {
 				match value.0 {
-					ResponseKind::Tcp(flags) => {
+					ResponseKind::Tcp { flags, .. } => {
 						if flags & TcpFlags::RST != 0 {
 							Ok(Self::Closed)
-						} else if flags & TcpFlags::SYN != 0 && flags & TcpFlags::SYN != 0 {
+						} else if flags & TcpFlags::SYN != 0 && flags & TcpFlags::ACK != 0 {
 							Ok(Self::Open)
 						} else {
 							Err(())
@@ -53,7 +62,7 @@ impl TryFrom<(ResponseKind, ScanType)> for PortStatus {
 			},
 			ScanType::ACK => {
 				match value.0 {
-					ResponseKind::Tcp(flags) => {
+					ResponseKind::Tcp { flags, .. } => {
 						if flags & TcpFlags::RST != 0 {
 							Ok(Self::Unfiltered)
 						} else {
@@ -71,6 +80,81 @@ impl TryFrom<(ResponseKind, ScanType)> for PortStatus {
 					_ => Err(())
 				}
 			},
+			// Some systems give RSTs a window when the port is open
+			ScanType::WINDOW => {
+				match value.0 {
+					ResponseKind::Tcp { flags, window } => {
+						if flags & TcpFlags::RST == 0 {
+							Err(())
+						} else if window != 0 {
+							Ok(Self::Open)
+						} else {
+							Ok(Self::Closed)
+						}
+					},
+					ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, code) => {
+						if ACCEPTED_ICMP_CODES.contains(&code) {
+							Ok(Self::Filtered)
+						} else {
+							Err(())
+						}
+					},
+					ResponseKind::NoResponse => Ok(Self::Filtered),
+					_ => Err(())
+				}
+			},
+			ScanType::INIT | ScanType::COOKIE => {
+				match value.0 {
+					ResponseKind::Sctp(sctp::ABORT) => Ok(Self::Closed),
+					ResponseKind::Sctp(sctp::INIT_ACK) if value.1 == ScanType::INIT => Ok(Self::Open),
+					ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, code) => {
+						if ACCEPTED_ICMP_CODES.contains(&code) {
+							Ok(Self::Filtered)
+						} else {
+							Err(())
+						}
+					},
+					// Open ports silently drop cookies they never gave out
+					ResponseKind::NoResponse if value.1 == ScanType::COOKIE => Ok(Self::OpenOrFiltered),
+					ResponseKind::NoResponse => Ok(Self::Filtered),
+					_ => Err(())
+				}
+			},
+			ScanType::PROTO => {
+				match value.0 {
+					ResponseKind::Protocol => Ok(Self::Open),
+					ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, code) => {
+						match code {
+							IcmpCodes::DestinationProtocolUnreachable => Ok(Self::Closed),
+							IcmpCodes::DestinationPortUnreachable => Ok(Self::Open), // UDP made it
+							_ if ACCEPTED_ICMP_CODES.contains(&code) => Ok(Self::Filtered),
+							_ => Err(())
+						}
+					},
+					ResponseKind::NoResponse => Ok(Self::OpenOrFiltered),
+					_ => Err(())
+				}
+			},
+			// The zombie sends a RST of its own to ports that answered its SYN/ACK
+			// closed ports answer with a RST, which it keeps quiet about, as filtered ones do
+			ScanType::IDLE => {
+				match value.0 {
+					ResponseKind::IpId(2) => Ok(Self::Open),
+					ResponseKind::IpId(1) => Ok(Self::ClosedOrFiltered),
+					ResponseKind::NoResponse => Ok(Self::Filtered),
+					_ => Err(())
+				}
+			},
+			// The server either opened the data connection to the port or could not
+			// NoResponse also stands for PORT commands the server refused
+			ScanType::BOUNCE => {
+				match value.0 {
+					ResponseKind::Ftp(125 | 150 | 226 | 250) => Ok(Self::Open),
+					ResponseKind::Ftp(425) => Ok(Self::Closed),
+					ResponseKind::NoResponse => Ok(Self::Filtered),
+					_ => Err(())
+				}
+			},
 			ScanType::UDP => {
 				match value.0 {
 					ResponseKind::Udp => Ok(Self::Open),
@@ -88,9 +172,9 @@ impl TryFrom<(ResponseKind, ScanType)> for PortStatus {
 					_ => Err(())
 				}
 			},
-			_ => { // NULL, FIN or XMAS
+			_ => { // NULL, FIN, XMAS or MAIMON, some BSDs drop FIN/ACK probes to open ports
 				match value.0 {
-					ResponseKind::Tcp(flags) => {
+					ResponseKind::Tcp { flags, .. } => {
 						if flags & TcpFlags::RST != 0 {
 							Ok(Self::Closed)
 						} else {
@@ -112,11 +196,61 @@ impl TryFrom<(ResponseKind, ScanType)> for PortStatus {
 	}		
 }
 
+impl PortStatus {
+	// CUSTOM scans send any flags, so their answers are read as told
+	fn custom(kind: ResponseKind, silence: Silence, reset: Reset) -> Result<Self, ()> {
+		match kind {
+			ResponseKind::Tcp { flags, .. } => {
+				if flags & TcpFlags::RST != 0 {
+					match reset {
+						Reset::Closed => Ok(Self::Closed),
+						Reset::Unfiltered => Ok(Self::Unfiltered)
+					}
+				} else if flags & TcpFlags::SYN != 0 && flags & TcpFlags::ACK != 0 {
+					Ok(Self::Open)
+				} else {
+					Err(())
+				}
+			},
+			ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, code) => {
+				if ACCEPTED_ICMP_CODES.contains(&code) {
+					Ok(Self::Filtered)
+				} else {
+					Err(())
+				}
+			},
+			ResponseKind::NoResponse => match silence {
+				Silence::OpenFiltered => Ok(Self::OpenOrFiltered),
+				Silence::Filtered => Ok(Self::Filtered)
+			},
+			_ => Err(())
+		}
+	}
+
+	// Whether the host actually answered for this port
+	fn is_responsive(&self) -> bool {
+		matches!(self, Self::Unfiltered | Self::Closed | Self::Open)
+	}
+
+	// Same as Display without spaces, for structured output
+	fn keyword(&self) -> &'static str {
+		match self {
+			PortStatus::Open => "open",
+			PortStatus::OpenOrFiltered => "open|filtered",
+			PortStatus::ClosedOrFiltered => "closed|filtered",
+			PortStatus::Closed => "closed",
+			PortStatus::Unfiltered => "unfiltered",
+			PortStatus::Filtered => "filtered"
+		}
+	}
+}
+
 impl Display for PortStatus {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(f, "{}", match self {
 			PortStatus::Open => "open",
 			PortStatus::OpenOrFiltered => "open | filtered",
+			PortStatus::ClosedOrFiltered => "closed | filtered",
 			PortStatus::Closed => "closed",
 			PortStatus::Unfiltered => "unfiltered",
 			PortStatus::Filtered => "filtered"
@@ -124,102 +258,464 @@ impl Display for PortStatus {
 	}
 }
 
-enum ProbeStatus {
-	Waiting(Instant),
-	TimedOut,
-	Done
-}
-
+// Compact on purpose, there is one per port and protocol of every host
 struct Report {
 	status: PortStatus,
-	probes: HashMap<u16, (ProbeStatus, ScanType)>
+	// Scans still waiting for an answer, one bit per scan type
+	pending: u32,
+	hostnames: Option<Arc<Vec<String>>>
 }
 
+fn scan_bit(scan: ScanType) -> u32 {
+	1 << (scan as u8)
+}
+
+// "db.internal (10.1.2.3):5432" or "10.1.2.3:5432" when the target was given as an address
+fn format_target(addr: &SocketAddr, hostnames: &Option<Arc<Vec<String>>>) -> String {
+	match hostnames {
+		Some(names) => format!("{} ({}):{}", names.join(","), addr.ip(), addr.port()),
+		None => addr.to_string()
+	}
+}
+
+// One JSON object per port, can be imported back with --import
+fn json_line(addr: &SocketAddr, protocol: Protocol, report: &Report, ptr: Option<&String>) -> String {
+	let mut line = json!({
+		"ip": addr.ip().to_string(),
+		"port": addr.port(),
+		"proto": protocol.to_string(),
+		"status": report.status.keyword()
+	});
+
+	if let Some(hostnames) = &report.hostnames {
+		line["hostnames"] = json!(hostnames.as_slice());
+	}
+	if let Some(ptr) = ptr {
+		line["ptr"] = json!(ptr);
+	}
+	line.to_string()
+}
+
+// Reports are kept apart for each protocol
+// a TCP and a UDP port with the same number are two different services
 pub struct Scanner {
-	inner: HashMap<SocketAddr, Report>
+	inner: HashMap<(SocketAddr, Protocol), Report>,
+	// Probe ids only depend on the scan type
+	scans: HashMap<u16, ScanType>,
+	// Probes are sent one after the other and all wait for the same time
+	// so the oldest one is always the next to time out
+	timeouts: VecDeque<(Instant, SocketAddr, ScanType)>,
+	waiting: usize,
+	custom: (Silence, Reset),
+	rdns: Option<ReverseResolver>
+}
+
+impl Default for Scanner {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Scanner {
 	pub fn new() -> Self {
-		Self { inner: HashMap::new() }
+		Self { inner: HashMap::new(), scans: HashMap::new(), timeouts: VecDeque::new(), waiting: 0, custom: Default::default(), rdns: None }
+	}
+
+	// How answers to CUSTOM scans are read
+	pub fn read_custom_as(&mut self, silence: Silence, reset: Reset) {
+		self.custom = (silence, reset);
+	}
+
+	fn status(&self, kind: ResponseKind, scan: ScanType) -> Result<PortStatus, ()> {
+		match scan {
+			ScanType::CUSTOM => PortStatus::custom(kind, self.custom.0, self.custom.1),
+			_ => PortStatus::try_from((kind, scan))
+		}
+	}
+
+	// Look up PTR records of hosts that answered, while scanning
+	pub fn enable_reverse_dns(&mut self, client: Option<Arc<dns::Client>>) {
+		self.rdns = Some(ReverseResolver::with_client(client));
 	}
 
 	pub fn add(&mut self, packet: Probe) {
-		match self.inner.get_mut(&packet.destination) {
-			Some(report) => {
-				report.probes.insert(packet.source_port, (ProbeStatus::Waiting(Instant::now()), packet.scan));
-			},
-			None => {
-				// Default status is "Filtered" because
-				// it has the least priority so it will be overwritten by any other value
-				let mut report = Report {
-					status: PortStatus::Filtered,
-					probes: HashMap::new()
-				};
-				report.probes.insert(packet.source_port, (ProbeStatus::Waiting(Instant::now()), packet.scan));
-				
-				self.inner.insert(packet.destination, report);
-			}
-		};
+		let key = (packet.destination, packet.scan.protocol());
+		// Default status is "Filtered" because
+		// it has the least priority so it will be overwritten by any other value
+		let report = self.inner.entry(key).or_insert_with(|| Report {
+			status: PortStatus::Filtered,
+			pending: 0,
+			hostnames: packet.hostnames
+		});
+
+		if report.pending & scan_bit(packet.scan) == 0 {
+			report.pending |= scan_bit(packet.scan);
+			self.waiting += 1;
+		}
+		self.scans.insert(packet.source_port, packet.scan);
+		self.timeouts.push_back((Instant::now(), packet.destination, packet.scan));
 	}
 
 	pub fn update(&mut self, packet: &[u8]) {
-		let response = match Response::try_from(packet) {
-			Ok(r) => r,
-			Err(_) => return
-		};
+		if let Ok(response) = Response::try_from(packet) {
+			self.record(response);
+		}
 
-		let report = match self.inner.get_mut(&response.origin) {
-			Some(r) => r,
-			None => return
-		};
+		// Any packet may answer an IP protocol probe
+		if self.scans.values().any(|scan| *scan == ScanType::PROTO) {
+			if let Ok((origin, kind)) = response::protocol_answer(packet) {
+				self.record_scan(origin, ScanType::PROTO, kind);
+			}
+		}
+	}
 
-		let probe = match report.probes.get_mut(&response.probe_id) {
-			Some(p) => p,
-			None => return
-		};
-		
+	// Connect scan outcomes don't come from packets
+	pub fn record(&mut self, response: Response) {
+		if let Some(scan) = self.scans.get(&response.probe_id) {
+			self.record_scan(response.origin, *scan, response.kind);
+		}
+	}
+
+	fn record_scan(&mut self, origin: SocketAddr, scan: ScanType, kind: ResponseKind) {
 		// If the response does not give any information
 		// about the port status, we keep waiting for new responses
-		let scan = probe.1;
-		let status = match PortStatus::try_from((response.kind, scan)) {
+		let status = match self.status(kind, scan) {
 			Ok(st) => st,
 			Err(_) => return
 		};
-		probe.0 = ProbeStatus::Done;
+		let report = match self.inner.get_mut(&(origin, scan.protocol())) {
+			Some(r) => r,
+			None => return
+		};
+		if report.pending & scan_bit(scan) != 0 {
+			report.pending &= !scan_bit(scan);
+			self.waiting -= 1;
+		}
 
 		// Port status can be represented as u8
 		// they're ranked from least to most accurate
 		if report.status < status {
 			report.status = status;
 		}
+
+		if let Some(rdns) = self.rdns.as_mut() {
+			if report.status.is_responsive() {
+				rdns.request(origin.ip());
+			}
+		}
+	}
+
+	// Gives up on probes that got no answer in time
+	// only looks at probes that are old enough, so it can be called after every packet
+	pub fn expire(&mut self) {
+		while let Some(&(time, destination, scan)) = self.timeouts.front() {
+			if time.elapsed() <= DEFAULT_TIMEOUT {
+				break ;
+			}
+			self.timeouts.pop_front();
+
+			let status = self.status(ResponseKind::NoResponse, scan).unwrap();
+			let report = match self.inner.get_mut(&(destination, scan.protocol())) {
+				Some(r) if r.pending & scan_bit(scan) != 0 => r,
+				_ => continue // answered in time
+			};
+			report.pending &= !scan_bit(scan);
+			self.waiting -= 1;
+
+			if report.status < status {
+				report.status = status;
+			}
+		}
 	}
 
 	pub fn is_complete(&mut self) -> bool {
-		let mut complete = true;
-
-		for report in self.inner.values_mut() {
-			for probe in report.probes.values_mut() {
-				if let ProbeStatus::Waiting(time) = probe.0 {
-					if time.elapsed() > DEFAULT_TIMEOUT {
-						probe.0 = ProbeStatus::TimedOut;
-						let status = PortStatus::try_from((ResponseKind::NoResponse, probe.1)).unwrap();
-						if report.status < status {
-							report.status = status;
-						}
-					} else {
-						complete = false;
-					}
-				}
+		self.expire();
+		self.waiting == 0
+	}
+
+	// Prints results and saves them to output, if any
+	pub fn print(self, output: Option<&str>) -> Result<()> {
+		let ptr = self.rdns.map(|rdns| rdns.finish()).unwrap_or_default();
+		let mut file = match output {
+			Some(path) => Some(std::io::BufWriter::new(std::fs::File::create(path)?)),
+			None => None
+		};
+
+		for ((addr, protocol), report) in self.inner.iter() {
+			let name = ptr.get(&addr.ip());
+			let hostnames = match &report.hostnames {
+				None => name.map(|name| Arc::new(vec![name.clone()])),
+				hostnames => hostnames.clone()
+			};
+			let service = services::table().name(addr.port(), *protocol).unwrap_or("unknown");
+			println!("{}/{protocol} {service} {}", format_target(addr, &hostnames), report.status);
+
+			if let Some(file) = file.as_mut() {
+				writeln!(file, "{}", json_line(addr, *protocol, report, name))?;
 			}
 		}
 
-		complete
+		if let Some(mut file) = file {
+			file.flush()?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+	use std::sync::Arc;
+	use super::{format_target, json_line, PortStatus, Report, Scanner};
+	use super::{sctp, ResponseKind};
+	use crate::iterators::scans::{Reset, Silence};
+	use crate::iterators::{Protocol, ScanType};
+	use crate::probes::{import, Probe, ProbeBuilder};
+	use crate::{cli, DEFAULT_TIMEOUT};
+	use clap::Parser;
+
+	fn probe(destination: SocketAddr, scan: ScanType) -> Probe {
+		Probe { data: vec![], destination, source_port: 40000 + scan as u16, scan, hostnames: None }
 	}
 
-	pub fn print(self) {
-		for report in self.inner.iter() {
-			println!("{} is {}", report.0, report.1.status);
+	// IPv4 and TCP headers of an answer from addr
+	fn tcp_answer(addr: SocketAddr, probe_id: u16, flags: u8) -> Vec<u8> {
+		let mut packet = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0];
+		packet.extend(match addr.ip() { std::net::IpAddr::V4(ip) => ip.octets(), _ => unreachable!() });
+		packet.extend([10, 0, 0, 1]);
+		packet.extend(addr.port().to_be_bytes());
+		packet.extend(probe_id.to_be_bytes());
+		packet.extend([0, 0, 0, 0, 0, 0, 0, 0, 5 << 4, flags, 0, 0, 0, 0, 0, 0]);
+		packet
+	}
+
+	#[test]
+	fn scanner_timeouts() {
+		let mut scanner = Scanner::new();
+		let open = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 22));
+		let silent = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 23));
+
+		scanner.add(probe(open, ScanType::SYN));
+		scanner.add(probe(silent, ScanType::SYN));
+		scanner.add(probe(silent, ScanType::UDP));
+		assert!(!scanner.is_complete());
+
+		scanner.update(&tcp_answer(open, 40000, 0x12)); // SYN-ACK
+		scanner.update(&tcp_answer(silent, 40000 + ScanType::UDP as u16, 0x12)); // not a TCP probe
+		assert_eq!(scanner.waiting, 2);
+
+		std::thread::sleep(DEFAULT_TIMEOUT);
+		assert!(scanner.is_complete());
+		assert!(scanner.timeouts.is_empty());
+		assert!(scanner.inner[&(open, Protocol::Tcp)].status == PortStatus::Open);
+		assert!(scanner.inner[&(silent, Protocol::Tcp)].status == PortStatus::Filtered);
+		assert!(scanner.inner[&(silent, Protocol::Udp)].status == PortStatus::OpenOrFiltered);
+	}
+
+	// ICMPv6 errors quoting a UDP probe from the UDP scan port
+	#[test]
+	fn scanner_icmpv6_errors() {
+		let ipv6 = |next: u8, source: [u8; 16], destination: [u8; 16], payload: &[u8]| {
+			let mut packet = vec![0x60, 0, 0, 0];
+			packet.extend((payload.len() as u16).to_be_bytes());
+			packet.extend([next, 64]);
+			packet.extend(source);
+			packet.extend(destination);
+			packet.extend(payload);
+			packet
+		};
+		let (local, target) = ("2001:db8::1".parse::<Ipv6Addr>().unwrap(), "2001:db8::2".parse::<Ipv6Addr>().unwrap());
+		let mut scanner = Scanner::new();
+		let (dns, ntp) = (SocketAddr::from((target, 53)), SocketAddr::from((target, 123)));
+		scanner.add(probe(dns, ScanType::UDP));
+		scanner.add(probe(ntp, ScanType::UDP));
+
+		for (addr, header) in [(dns, [4, 1, 0, 0, 0, 0, 0, 6]), (ntp, [2, 0, 0, 0, 0, 0, 0x05, 0x00])] {
+			let mut udp = (40000 + ScanType::UDP as u16).to_be_bytes().to_vec();
+			udp.extend(addr.port().to_be_bytes());
+			udp.extend([0, 8, 0, 0]);
+			let mut icmp = header.to_vec();
+			icmp.extend(ipv6(17, local.octets(), target.octets(), &udp));
+			scanner.update(&ipv6(58, target.octets(), local.octets(), &icmp));
+		}
+
+		// Unrecognized next header is protocol unreachable, packet too big is ignored
+		std::thread::sleep(DEFAULT_TIMEOUT);
+		assert!(scanner.is_complete());
+		assert!(scanner.inner[&(dns, Protocol::Udp)].status == PortStatus::Filtered);
+		assert!(scanner.inner[&(ntp, Protocol::Udp)].status == PortStatus::OpenOrFiltered);
+	}
+
+	#[test]
+	fn scanner_window_and_maimon() {
+		let mut scanner = Scanner::new();
+		let (open, closed) = (SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 22)), SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 23)));
+		let window_id = 40000 + ScanType::WINDOW as u16;
+
+		scanner.add(probe(open, ScanType::WINDOW));
+		scanner.add(probe(closed, ScanType::WINDOW));
+		let mut rst = tcp_answer(open, window_id, 0x04);
+		rst[34..36].copy_from_slice(&1024u16.to_be_bytes()); // window of the TCP header
+		scanner.update(&rst);
+		scanner.update(&tcp_answer(closed, window_id, 0x04));
+		assert!(scanner.inner[&(open, Protocol::Tcp)].status == PortStatus::Open);
+		assert!(scanner.inner[&(closed, Protocol::Tcp)].status == PortStatus::Closed);
+
+		let maimon = |kind| PortStatus::try_from((kind, ScanType::MAIMON));
+		assert!(maimon(ResponseKind::NoResponse) == Ok(PortStatus::OpenOrFiltered));
+		assert!(maimon(ResponseKind::Tcp { flags: 0x04, window: 0 }) == Ok(PortStatus::Closed));
+		assert!(PortStatus::try_from((ResponseKind::NoResponse, ScanType::WINDOW)) == Ok(PortStatus::Filtered));
+	}
+
+	// Answers to probes from ProbeBuilder, their ports swapped, go back to the scan that sent them
+	#[test]
+	fn scanner_probe_ports() {
+		let arguments = vec![clap::crate_name!(), "-i 10.0.0.2", "-p T:22,U:53", "-s SYN,ACK,UDP"];
+		let builder = ProbeBuilder::new(cli::Args::try_parse_from(arguments).unwrap(), Some([10, 0, 0, 1].into())).unwrap();
+		let mut scanner = Scanner::new();
+		let mut answers = vec![];
+
+		for probe in builder {
+			let mut answer = probe.data.clone();
+			(0..4).for_each(|i| answer.swap(12 + i, 16 + i)); // IP addresses
+			(0..2).for_each(|i| answer.swap(20 + i, 22 + i)); // ports
+			match probe.scan {
+				ScanType::SYN => continue, // left unanswered
+				ScanType::ACK => answer[33] = 0x04, // RST
+				_ => {}
+			}
+			answers.push(answer);
+			scanner.add(probe);
+		}
+		answers.iter().for_each(|answer| scanner.update(answer));
+
+		let (tcp, udp) = (SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 22)), SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 53)));
+		assert!(scanner.inner[&(tcp, Protocol::Tcp)].status == PortStatus::Unfiltered);
+		assert!(scanner.inner[&(udp, Protocol::Udp)].status == PortStatus::Open);
+	}
+
+	#[test]
+	fn scanner_custom_flags() {
+		let mut scanner = Scanner::new();
+		let (silent, reset) = (SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 22)), SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 23)));
+
+		scanner.read_custom_as(Silence::Filtered, Reset::Unfiltered);
+		scanner.add(probe(silent, ScanType::CUSTOM));
+		scanner.add(probe(reset, ScanType::CUSTOM));
+		scanner.update(&tcp_answer(reset, 40000 + ScanType::CUSTOM as u16, 0x04));
+		std::thread::sleep(DEFAULT_TIMEOUT);
+		assert!(scanner.is_complete());
+		assert!(scanner.inner[&(silent, Protocol::Tcp)].status == PortStatus::Filtered);
+		assert!(scanner.inner[&(reset, Protocol::Tcp)].status == PortStatus::Unfiltered);
+
+		let default = |kind| PortStatus::custom(kind, Silence::default(), Reset::default());
+		assert!(default(ResponseKind::NoResponse) == Ok(PortStatus::OpenOrFiltered));
+		assert!(default(ResponseKind::Tcp { flags: 0x14, window: 0 }) == Ok(PortStatus::Closed));
+		assert!(default(ResponseKind::Tcp { flags: 0x12, window: 0 }) == Ok(PortStatus::Open));
+	}
+
+	#[test]
+	fn sctp_statuses() {
+		let init = |kind| PortStatus::try_from((kind, ScanType::INIT));
+		let cookie = |kind| PortStatus::try_from((kind, ScanType::COOKIE));
+
+		assert!(init(ResponseKind::Sctp(sctp::INIT_ACK)) == Ok(PortStatus::Open));
+		assert!(init(ResponseKind::Sctp(sctp::ABORT)) == Ok(PortStatus::Closed));
+		assert!(init(ResponseKind::NoResponse) == Ok(PortStatus::Filtered));
+		assert!(cookie(ResponseKind::Sctp(sctp::ABORT)) == Ok(PortStatus::Closed));
+		assert!(cookie(ResponseKind::Sctp(sctp::INIT_ACK)).is_err());
+		assert!(cookie(ResponseKind::NoResponse) == Ok(PortStatus::OpenOrFiltered));
+	}
+
+	#[test]
+	fn idle_statuses() {
+		let idle = |kind| PortStatus::try_from((kind, ScanType::IDLE));
+		assert!(idle(ResponseKind::IpId(2)) == Ok(PortStatus::Open));
+		assert!(idle(ResponseKind::IpId(1)) == Ok(PortStatus::ClosedOrFiltered));
+		assert!(idle(ResponseKind::IpId(3)).is_err());
+		assert!(idle(ResponseKind::NoResponse) == Ok(PortStatus::Filtered));
+		assert_eq!(PortStatus::ClosedOrFiltered.keyword(), "closed|filtered");
+		assert!(PortStatus::ClosedOrFiltered < PortStatus::Closed);
+	}
+
+	#[test]
+	fn bounce_statuses() {
+		let bounce = |kind| PortStatus::try_from((kind, ScanType::BOUNCE));
+		assert!(bounce(ResponseKind::Ftp(150)) == Ok(PortStatus::Open));
+		assert!(bounce(ResponseKind::Ftp(226)) == Ok(PortStatus::Open));
+		assert!(bounce(ResponseKind::Ftp(425)) == Ok(PortStatus::Closed));
+		assert!(bounce(ResponseKind::Ftp(530)).is_err());
+		assert!(bounce(ResponseKind::NoResponse) == Ok(PortStatus::Filtered));
+	}
+
+	#[test]
+	fn protocol_statuses() {
+		let mut scanner = Scanner::new();
+		let (gre, esp, icmp) = (SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 47)), SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 50)), SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 1)));
+		for destination in [gre, esp, icmp] {
+			scanner.add(probe(destination, ScanType::PROTO));
 		}
+
+		// Protocol unreachable for GRE, echo reply
+		let mut unreachable = vec![0x45, 0, 0, 56, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1, 3, 2, 0, 0, 0, 0, 0, 0];
+		unreachable.extend([0x45, 0, 0, 20, 0, 0, 0, 0, 64, 47, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+		scanner.update(&unreachable);
+		scanner.update(&[0x45, 0, 0, 28, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+		std::thread::sleep(DEFAULT_TIMEOUT);
+		assert!(scanner.is_complete());
+		assert!(scanner.inner[&(gre, Protocol::Ip)].status == PortStatus::Closed);
+		assert!(scanner.inner[&(esp, Protocol::Ip)].status == PortStatus::OpenOrFiltered);
+		assert!(scanner.inner[&(icmp, Protocol::Ip)].status == PortStatus::Open);
+	}
+
+	#[test]
+	fn scanner_many_probes() {
+		let mut scanner = Scanner::new();
+
+		for host in 1..=5 {
+			for port in 1..=u16::MAX {
+				scanner.add(probe(SocketAddr::from((Ipv4Addr::new(10, 0, 0, host), port)), ScanType::SYN));
+			}
+		}
+		assert_eq!(scanner.waiting, 5 * 65535);
+		assert_eq!(scanner.scans.len(), 1);
+
+		std::thread::sleep(DEFAULT_TIMEOUT);
+		assert!(scanner.is_complete());
+		assert!(scanner.timeouts.is_empty());
+		assert!(scanner.inner.values().all(|report| report.status == PortStatus::Filtered && report.pending == 0));
+	}
+
+	#[test]
+	fn target_with_hostnames() {
+		let addr = SocketAddr::from((Ipv4Addr::new(10, 1, 2, 3), 5432));
+
+		assert_eq!(format_target(&addr, &None), "10.1.2.3:5432");
+		assert_eq!(format_target(&addr, &Some(Arc::new(vec![String::from("db.internal")]))), "db.internal (10.1.2.3):5432");
+		assert_eq!(
+			format_target(&addr, &Some(Arc::new(vec![String::from("db.internal"), String::from("pg.internal")]))),
+			"db.internal,pg.internal (10.1.2.3):5432"
+		);
+	}
+
+	#[test]
+	fn json_output_roundtrip() {
+		let addr = SocketAddr::from((Ipv4Addr::new(10, 1, 2, 3), 53));
+		let mut report = Report {
+			status: PortStatus::Open,
+			pending: 0,
+			hostnames: Some(Arc::new(vec![String::from("ns.\"internal\"")]))
+		};
+
+		let open = json_line(&addr, Protocol::Udp, &report, Some(&String::from("ns1.internal")));
+		assert_eq!(open, r#"{"hostnames":["ns.\"internal\""],"ip":"10.1.2.3","port":53,"proto":"udp","ptr":"ns1.internal","status":"open"}"#);
+
+		report.status = PortStatus::OpenOrFiltered;
+		let filtered = json_line(&addr, Protocol::Tcp, &report, None);
+		assert!(filtered.contains(r#""status":"open|filtered""#));
+
+		let imported = import::parse(&format!("{open}\n{filtered}\n")).unwrap();
+		assert_eq!(imported.endpoints, vec![(Ipv4Addr::new(10, 1, 2, 3).into(), Protocol::Udp, 53)]);
 	}
 }
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::time::Instant;
+use anyhow::{Result, anyhow};
+
+use crate::dns::{self, QueryType, Record};
+use crate::iterators::{hosts, HostRange};
+use crate::{RDNS_THREADS, RDNS_TIMEOUT, RESOLVE_THREADS};
+
+// Hostnames each resolved address was given as
+// shared with the thread reading streamed targets
+pub type Hostnames = Arc<Mutex<HashMap<IpAddr, Arc<Vec<String>>>>>;
+
+#[derive(Clone, Debug)]
+pub struct Resolver {
+	all: bool,
+	hostnames: Hostnames,
+	client: Option<Arc<dns::Client>>
+}
+
+impl Resolver {
+	// If all is false, only the first address of a hostname is kept, IPv4 addresses come first
+	// without a DNS client, hostnames go through the system resolver
+	pub fn new(all: bool, client: Option<Arc<dns::Client>>) -> Self {
+		Self { all, hostnames: Arc::default(), client }
+	}
+
+	pub fn hostnames(&self) -> Hostnames {
+		Arc::clone(&self.hostnames)
+	}
+
+	pub fn resolve(&self, target: &str) -> Result<Vec<HostRange>> {
+		self.resolve_many(&[target.to_string()]).pop().unwrap()
+	}
+
+	// Targets are either addresses, CIDR blocks, octet ranges or hostnames
+	// hostnames are looked up in parallel, results are in the same order as targets
+	pub fn resolve_many(&self, targets: &[String]) -> Vec<Result<Vec<HostRange>>> {
+		let names: Vec<String> = targets.iter()
+			.filter(|t| !hosts::is_spec(t))
+			.map(|t| t.trim().to_string())
+			.collect();
+		let mut lookups = self.lookup(&names).into_iter();
+
+		targets.iter().map(|target| {
+			if hosts::is_spec(target) {
+				return hosts::parse(target);
+			}
+
+			let mut addresses = lookups.next().unwrap()?;
+			if !self.all {
+				addresses.truncate(1);
+			}
+
+			let name = target.trim();
+			let mut hostnames = self.hostnames.lock().unwrap();
+			for addr in addresses.iter() {
+				let names = Arc::make_mut(hostnames.entry(*addr).or_default());
+				if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+					names.push(name.to_string());
+				}
+			}
+
+			Ok(addresses.into_iter().map(HostRange::from).collect())
+		}).collect()
+	}
+
+	// A and AAAA queries are sent together, a name only fails when both do
+	// the system resolver blocks, so names are split among RESOLVE_THREADS threads
+	fn lookup(&self, names: &[String]) -> Vec<Result<Vec<IpAddr>>> {
+		let client = match self.client.as_ref() {
+			Some(c) => c,
+			None => return std::thread::scope(|scope| {
+				let chunk = names.len().div_ceil(RESOLVE_THREADS).max(1);
+				let threads: Vec<_> = names.chunks(chunk).map(|names| {
+					scope.spawn(move || names.iter().map(|name| lookup_system(name)).collect::<Vec<_>>())
+				}).collect();
+				threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+			})
+		};
+
+		let queries: Vec<(&str, QueryType)> = names.iter().flat_map(|name| [(name.as_str(), QueryType::A), (name.as_str(), QueryType::Aaaa)]).collect();
+		let mut results = client.query_each(&queries).into_iter();
+		names.iter().map(|name| {
+			let (ipv4, ipv6) = (results.next().unwrap(), results.next().unwrap());
+			let records = match (ipv4, ipv6) {
+				(Err(e), Err(_)) => return Err(e),
+				(ipv4, ipv6) => ipv4.unwrap_or_default().into_iter().chain(ipv6.unwrap_or_default())
+			};
+			let addresses: Vec<IpAddr> = records.filter_map(|record| match record {
+				Record::A(ipv4) => Some(ipv4.into()),
+				Record::Aaaa(ipv6) => Some(ipv6.into()),
+				_ => None
+			}).collect();
+
+			match addresses.is_empty() {
+				true => Err(anyhow!("\"{name}\" has no IP address")),
+				false => Ok(addresses)
+			}
+		}).collect()
+	}
+}
+
+// IPv4 addresses first, as they are the ones kept without --resolve-all
+fn lookup_system(name: &str) -> Result<Vec<IpAddr>> {
+	let mut addresses: Vec<IpAddr> = vec![];
+	for ip in dns_lookup::lookup_host(name).unwrap_or_default().into_iter() {
+		if !addresses.contains(&ip) { // one entry per socket type
+			addresses.push(ip);
+		}
+	}
+	addresses.sort_by_key(IpAddr::is_ipv6);
+
+	match addresses.is_empty() {
+		true => Err(anyhow!("\"{name}\" does not represent any valid IP address")),
+		false => Ok(addresses)
+	}
+}
+
+// PTR lookup through the system resolver
+fn lookup_ptr(addr: IpAddr) -> Option<String> {
+	let sock = SocketAddr::from((addr, 0));
+	dns_lookup::getnameinfo(&sock, libc::NI_NAMEREQD | libc::NI_NUMERICSERV).ok().map(|(name, _)| name)
+}
+
+pub type PtrLookup = Arc<dyn Fn(IpAddr) -> Option<String> + Send + Sync>;
+
+#[derive(Default)]
+struct PtrResults {
+	names: HashMap<IpAddr, String>,
+	pending: usize
+}
+
+/*
+** Reverse DNS lookups run by a pool of worker threads
+** requests never block, so this can be fed from the packet loop
+** each address is only looked up once
+*/
+pub struct ReverseResolver {
+	requests: mpsc::Sender<IpAddr>,
+	requested: HashSet<IpAddr>,
+	results: Arc<(Mutex<PtrResults>, Condvar)>
+}
+
+impl Default for ReverseResolver {
+	fn default() -> Self {
+		Self::new(RDNS_THREADS, Arc::new(lookup_ptr))
+	}
+}
+
+impl ReverseResolver {
+	// Lookups go through the DNS client when there is one
+	pub fn with_client(client: Option<Arc<dns::Client>>) -> Self {
+		let client = match client {
+			Some(c) => c,
+			None => return Self::default()
+		};
+
+		Self::new(RDNS_THREADS, Arc::new(move |addr| {
+			client.query(&dns::ptr_name(addr), QueryType::Ptr).ok()?.into_iter().find_map(|record| match record {
+				Record::Ptr(name) => Some(name),
+				_ => None
+			})
+		}))
+	}
+
+	pub fn new(threads: usize, lookup: PtrLookup) -> Self {
+		let (tx, rx) = mpsc::channel::<IpAddr>();
+		let rx = Arc::new(Mutex::new(rx));
+		let results: Arc<(Mutex<PtrResults>, Condvar)> = Arc::default();
+
+		for _ in 0..threads {
+			let rx = Arc::clone(&rx);
+			let results = Arc::clone(&results);
+			let lookup = Arc::clone(&lookup);
+
+			std::thread::spawn(move || loop {
+				let addr = match rx.lock().unwrap().recv() {
+					Ok(addr) => addr,
+					Err(_) => return // resolver was dropped
+				};
+				let name = lookup(addr);
+
+				let (state, done) = &*results;
+				let mut state = state.lock().unwrap();
+				if let Some(name) = name {
+					state.names.insert(addr, name);
+				}
+				state.pending -= 1;
+				done.notify_all();
+			});
+		}
+
+		Self { requests: tx, requested: HashSet::new(), results }
+	}
+
+	pub fn request(&mut self, addr: IpAddr) {
+		if !self.requested.insert(addr) {
+			return ; // already asked for
+		}
+
+		self.results.0.lock().unwrap().pending += 1;
+		if self.requests.send(addr).is_err() {
+			self.results.0.lock().unwrap().pending -= 1;
+		}
+	}
+
+	// Waits for pending lookups, at most RDNS_TIMEOUT
+	// lookups still running after that are left out
+	pub fn finish(self) -> HashMap<IpAddr, String> {
+		let deadline = Instant::now() + RDNS_TIMEOUT;
+		let (state, done) = &*self.results;
+		let mut state = state.lock().unwrap();
+
+		while state.pending > 0 {
+			let now = Instant::now();
+			if now >= deadline {
+				break ;
+			}
+			state = done.wait_timeout(state, deadline - now).unwrap().0;
+		}
+
+		std::mem::take(&mut state.names)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::time::Duration;
+	use super::{Resolver, ReverseResolver};
+	use crate::dns;
+	use crate::iterators::HostRange;
+
+	#[test]
+	fn resolve_specs() {
+		let resolver = Resolver::new(false, None);
+
+		assert_eq!(resolver.resolve("10.0.0.0/31").unwrap(), vec![HostRange::try_from("10.0.0.0/31").unwrap()]);
+		assert!(resolver.resolve("10.0.0.300").is_err());
+		assert!(resolver.hostnames().lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn resolve_keeps_hostnames() {
+		let resolver = Resolver::new(true, None);
+
+		assert!(resolver.resolve("localhost").unwrap().contains(&HostRange::from(Ipv4Addr::LOCALHOST)));
+		assert!(resolver.resolve(" LOCALHOST ").is_ok());
+
+		let hostnames = resolver.hostnames();
+		let hostnames = hostnames.lock().unwrap();
+		assert_eq!(hostnames.get(&IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap().as_slice(), ["localhost"]);
+	}
+
+	#[test]
+	fn resolve_with_client() {
+		let client = dns::Client::new(vec![dns::test::fake_server()], Duration::from_millis(100));
+		let resolver = Resolver::new(true, Some(Arc::new(client)));
+		let targets: Vec<String> = ["many.test", "10.0.0.9", "nx.test"].iter().map(|s| s.to_string()).collect();
+		let results = resolver.resolve_many(&targets);
+
+		assert_eq!(results[0].as_ref().unwrap(), &vec![
+			HostRange::from(Ipv4Addr::new(10, 0, 0, 2)),
+			HostRange::from(Ipv4Addr::new(10, 0, 0, 3)),
+			HostRange::from("2001:db8::1".parse::<IpAddr>().unwrap())
+		]);
+		assert_eq!(results[1].as_ref().unwrap(), &vec![HostRange::from(Ipv4Addr::new(10, 0, 0, 9))]);
+		assert!(results[2].as_ref().unwrap_err().to_string().contains("NXDOMAIN"));
+
+		let hostnames = resolver.hostnames();
+		assert_eq!(hostnames.lock().unwrap().get(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3))).unwrap().as_slice(), ["many.test"]);
+
+		// Names with IPv6 addresses only
+		assert_eq!(resolver.resolve("v6.test").unwrap(), vec![HostRange::from("2001:db8::1".parse::<IpAddr>().unwrap())]);
+		assert_eq!(Resolver::new(false, resolver.client.clone()).resolve("many.test").unwrap(), vec![HostRange::from(Ipv4Addr::new(10, 0, 0, 2))]); // IPv4 first
+	}
+
+	#[test]
+	fn resolve_unknown_hostname() {
+		assert!(Resolver::new(true, None).resolve("non.existing.domain.invalid").is_err());
+	}
+
+	#[test]
+	fn resolve_system_in_order() {
+		let targets: Vec<String> = (0..40).map(|n| match n % 3 {
+			0 => String::from("localhost"),
+			1 => format!("10.0.0.{n}"),
+			_ => String::from("non.existing.domain.invalid")
+		}).collect();
+		let results = Resolver::new(false, None).resolve_many(&targets);
+
+		assert_eq!(results.len(), 40);
+		for (n, result) in results.iter().enumerate() {
+			match n % 3 {
+				0 => assert!(result.as_ref().unwrap().contains(&HostRange::from(Ipv4Addr::LOCALHOST))),
+				1 => assert_eq!(result.as_ref().unwrap(), &vec![HostRange::from(Ipv4Addr::new(10, 0, 0, n as u8))]),
+				_ => assert!(result.is_err())
+			}
+		}
+	}
+
+	#[test]
+	fn reverse_lookups_are_cached() {
+		static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+		fn lookup(addr: IpAddr) -> Option<String> {
+			LOOKUPS.fetch_add(1, Ordering::SeqCst);
+			match addr {
+				IpAddr::V4(addr) if addr.octets()[3] == 0 => None,
+				IpAddr::V4(addr) => Some(format!("host-{}.internal", addr.octets()[3])),
+				IpAddr::V6(_) => Some(String::from("host-v6.internal"))
+			}
+		}
+
+		let mut rdns = ReverseResolver::new(4, std::sync::Arc::new(lookup));
+		for _ in 0..3 {
+			for n in 0..4 {
+				rdns.request(Ipv4Addr::new(10, 0, 0, n).into());
+			}
+			rdns.request(Ipv6Addr::LOCALHOST.into());
+		}
+
+		let names = rdns.finish();
+		assert_eq!(LOOKUPS.load(Ordering::SeqCst), 5);
+		assert_eq!(names.len(), 4);
+		assert_eq!(names.get(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))).unwrap(), "host-2.internal");
+		assert_eq!(names.get(&IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap(), "host-v6.internal");
+		assert!(!names.contains_key(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0))));
+	}
+
+	#[test]
+	fn reverse_lookup_system() {
+		let mut rdns = ReverseResolver::default();
+		rdns.request(Ipv4Addr::LOCALHOST.into());
+		assert!(rdns.finish().contains_key(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+	}
+}
@@ -1,50 +1,156 @@
 use anyhow::{Result, anyhow};
 use std::{
-	net::SocketAddr,
+	net::{IpAddr, SocketAddr},
 	time::Instant
 };
 use pnet::packet::{
-	Packet,
-	icmp::{IcmpType, IcmpCode, IcmpPacket},
+	icmp::{IcmpType, IcmpTypes, IcmpCode, IcmpPacket},
+	icmp::destination_unreachable::IcmpCodes,
+	icmpv6::{Icmpv6Packet, Icmpv6Types},
 	ipv4::Ipv4Packet,
-	ip::IpNextHeaderProtocols,
+	ipv6::Ipv6Packet,
+	ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
 	tcp::TcpPacket,
 	udp::UdpPacket
 };
 
+use super::sctp;
+
 #[derive(Debug)]
 pub struct Response {
 	pub origin: SocketAddr,
 	pub probe_id: u16,
 	pub kind: ResponseKind,
-	pub time: Instant
+	pub time: Instant,
+	pub ip_id: Option<u16> // IPv4 identification, idle scans read zombies with it
 }
 
 #[derive(Debug)]
 pub enum ResponseKind {
 	NoResponse, // unused, yet ?
-	Tcp(u16),
+	Tcp { flags: u16, window: u16 },
 	Icmp(IcmpType, IcmpCode),
-	Udp
+	Udp,
+	Sctp(u8), // type of the first chunk
+	Protocol, // anything in the probed IP protocol
+	IpId(u16), // how far the IP-ID of a zombie moved across an idle probe
+	Ftp(u16) // reply of an FTP server told to send a listing to the port
 }
 
 struct NextHeaderInfo {
 	protocol: ResponseKind,
 	destination: u16,
-	source: u16
+	source: u16,
+	origin: Option<IpAddr>
+}
+
+// Network layer of a packet, whatever the IP version
+// payload starts at the transport header, after IPv6 extension headers
+struct NetworkInfo<'a> {
+	source: IpAddr,
+	destination: IpAddr,
+	protocol: IpNextHeaderProtocol,
+	identification: Option<u16>,
+	payload: &'a [u8]
 }
 
-fn fetch_next_header_info(ip: &Ipv4Packet) -> Result<NextHeaderInfo> {
-	let next = ip.payload();
+// ICMP errors may quote a truncated packet
+// so payloads end at the announced length or at the end of the buffer
+fn fetch_network_info(buffer: &[u8]) -> Result<NetworkInfo<'_>> {
+	match buffer.first().map(|byte| byte >> 4) {
+		Some(4) => {
+			let ip = Ipv4Packet::new(buffer).ok_or(anyhow!("Packet too small."))?;
+			let start = ip.get_header_length() as usize * 4;
+			let end = (ip.get_total_length() as usize).min(buffer.len());
+
+			Ok(NetworkInfo {
+				source: IpAddr::V4(ip.get_source()),
+				destination: IpAddr::V4(ip.get_destination()),
+				protocol: ip.get_next_level_protocol(),
+				identification: Some(ip.get_identification()),
+				payload: buffer.get(start..end.max(start)).ok_or(anyhow!("Packet too small."))?
+			})
+		},
+		Some(6) => {
+			let ip = Ipv6Packet::new(buffer).ok_or(anyhow!("Packet too small."))?;
+			let end = (40 + ip.get_payload_length() as usize).min(buffer.len());
+			let (protocol, payload) = skip_extension_headers(ip.get_next_header(), &buffer[40..end])?;
+
+			Ok(NetworkInfo {
+				source: IpAddr::V6(ip.get_source()),
+				destination: IpAddr::V6(ip.get_destination()),
+				protocol,
+				identification: None,
+				payload
+			})
+		},
+		_ => Err(anyhow!("Unsupported network protocol."))
+	}
+}
+
+// Follows the chain of IPv6 extension headers up to the upper-layer header
+fn skip_extension_headers(mut protocol: IpNextHeaderProtocol, mut payload: &[u8]) -> Result<(IpNextHeaderProtocol, &[u8])> {
+	loop {
+		let length = match protocol {
+			IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route | IpNextHeaderProtocols::Ipv6Opts => {
+				(*payload.get(1).ok_or(anyhow!("Packet too small."))? as usize + 1) * 8
+			},
+			IpNextHeaderProtocols::Ipv6Frag => {
+				let offset = u16::from_be_bytes([
+					*payload.get(2).ok_or(anyhow!("Packet too small."))?,
+					*payload.get(3).ok_or(anyhow!("Packet too small."))?
+				]) >> 3;
+				if offset != 0 {
+					return Err(anyhow!("Only the first fragment holds the upper-layer header."));
+				}
+				8
+			},
+			IpNextHeaderProtocols::Ah => (*payload.get(1).ok_or(anyhow!("Packet too small."))? as usize + 2) * 4,
+			_ => return Ok((protocol, payload))
+		};
+
+		protocol = IpNextHeaderProtocol(payload[0]);
+		payload = payload.get(length..).ok_or(anyhow!("Packet too small."))?;
+	}
+}
+
+// Routers may only quote the first 8 bytes of the probe
+// which is enough for the ports of TCP, UDP and SCTP
+fn fetch_quoted_ports(ip: &NetworkInfo) -> Result<(u16, u16)> {
+	match (ip.protocol, ip.payload) {
+		(IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp | IpNextHeaderProtocols::Sctp, [a, b, c, d, ..]) => {
+			Ok((u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d])))
+		},
+		_ => Err(anyhow!("Unsupported quoted protocol."))
+	}
+}
 
-	let info = match ip.get_next_level_protocol() {
+// ICMPv6 errors are translated to their ICMPv4 counterpart
+// so that ports get the same status whatever the IP version
+fn icmpv6_kind(icmp: &Icmpv6Packet) -> Result<ResponseKind> {
+	let code = match (icmp.get_icmpv6_type(), icmp.get_icmpv6_code().0) {
+		(Icmpv6Types::DestinationUnreachable, 4) => IcmpCodes::DestinationPortUnreachable,
+		(Icmpv6Types::DestinationUnreachable, 1 | 5 | 6) => IcmpCodes::CommunicationAdministrativelyProhibited,
+		(Icmpv6Types::DestinationUnreachable, _) => IcmpCodes::DestinationHostUnreachable,
+		(Icmpv6Types::ParameterProblem, 1) => IcmpCodes::DestinationProtocolUnreachable, // unrecognized next header
+		_ => return Err(anyhow!("Unsupported ICMPv6 message."))
+	};
+
+	Ok(ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, code))
+}
+
+fn fetch_next_header_info(ip: &NetworkInfo) -> Result<NextHeaderInfo> {
+	let next = ip.payload;
+
+	let info = match ip.protocol {
 		IpNextHeaderProtocols::Tcp => {
 			let tcp = TcpPacket::new(next).ok_or(anyhow!("Packet too small."))?;
 
 			(
-				ResponseKind::Tcp(tcp.get_flags()),
+				ResponseKind::Tcp { flags: tcp.get_flags(), window: tcp.get_window() },
 				tcp.get_destination(),
-				tcp.get_source()
+				tcp.get_source(),
+				None
 			)
 		},
 		IpNextHeaderProtocols::Udp => {
@@ -53,20 +159,44 @@ fn fetch_next_header_info(ip: &Ipv4Packet) -> Result<NextHeaderInfo> {
 			(
 				ResponseKind::Udp,
 				udp.get_destination(),
-				udp.get_source()
+				udp.get_source(),
+				None
+			)
+		},
+		IpNextHeaderProtocols::Sctp => {
+			let (source, destination, chunk) = sctp::parse(next).ok_or(anyhow!("Packet too small."))?;
+
+			(
+				ResponseKind::Sctp(chunk),
+				destination,
+				source,
+				None
 			)
 		},
-		IpNextHeaderProtocols::Icmp => {
-			let icmp = IcmpPacket::new(next).ok_or(anyhow!("Packet too small."))?;
-			let ip = Ipv4Packet::new(icmp.payload()).ok_or(anyhow!("Packet too small."))?;
-			let origin_info = fetch_next_header_info(&ip)?;
+		IpNextHeaderProtocols::Icmp | IpNextHeaderProtocols::Icmpv6 => {
+			// Both headers are 4 bytes long, followed by 4 bytes
+			// that are unused or hold the MTU, then comes the quoted probe
+			let (kind, quoted) = match ip.protocol {
+				IpNextHeaderProtocols::Icmp => {
+					let icmp = IcmpPacket::new(next).ok_or(anyhow!("Packet too small."))?;
+					(ResponseKind::Icmp(icmp.get_icmp_type(), icmp.get_icmp_code()), next.get(8..))
+				},
+				_ => {
+					let icmp = Icmpv6Packet::new(next).ok_or(anyhow!("Packet too small."))?;
+					(icmpv6_kind(&icmp)?, next.get(8..))
+				}
+			};
+			let quoted = fetch_network_info(quoted.ok_or(anyhow!("Packet too small."))?)?;
+			let (source, destination) = fetch_quoted_ports(&quoted)?;
 
 			(
-				ResponseKind::Icmp(icmp.get_icmp_type(), icmp.get_icmp_code()),
+				kind,
 				// This is inverted here because ICMP payload contains
 				// the original probe we sent earlier
-				origin_info.source,
-				origin_info.destination,
+				source,
+				destination,
+				// The error may come from a router on the way
+				Some(quoted.destination)
 			)
 		},
 		_ => return Err(anyhow!("Unsupported protocol."))
@@ -75,24 +205,208 @@ fn fetch_next_header_info(ip: &Ipv4Packet) -> Result<NextHeaderInfo> {
 	Ok(NextHeaderInfo {
 		protocol: info.0,
 		destination: info.1,
-		source: info.2
+		source: info.2,
+		origin: info.3
 	})
 }
 
+// Answers to IP protocol probes carry no usable port
+// they are told apart by protocol number instead, the port of their origin
+// ICMP errors are about the protocol of the probe they quote
+pub fn protocol_answer(buffer: &[u8]) -> Result<(SocketAddr, ResponseKind)> {
+	let ip = fetch_network_info(buffer)?;
+	if ip.protocol != IpNextHeaderProtocols::Icmp {
+		return Ok(((ip.source, ip.protocol.0 as u16).into(), ResponseKind::Protocol));
+	}
+
+	let icmp = IcmpPacket::new(ip.payload).ok_or(anyhow!("Packet too small."))?;
+	match icmp.get_icmp_type() {
+		IcmpTypes::DestinationUnreachable => {
+			let quoted = fetch_network_info(ip.payload.get(8..).ok_or(anyhow!("Packet too small."))?)?;
+			Ok(((quoted.destination, quoted.protocol.0 as u16).into(), ResponseKind::Icmp(icmp.get_icmp_type(), icmp.get_icmp_code())))
+		},
+		// Other errors may come from routers on the way
+		IcmpTypes::TimeExceeded | IcmpTypes::ParameterProblem | IcmpTypes::RedirectMessage | IcmpTypes::SourceQuench => {
+			Err(anyhow!("Unsupported ICMP message."))
+		},
+		_ => Ok(((ip.source, ip.protocol.0 as u16).into(), ResponseKind::Protocol))
+	}
+}
+
 impl TryFrom<&[u8]> for Response {
 	type Error = anyhow::Error;
 
 	fn try_from(buffer: &[u8]) -> Result<Self, <Self as TryFrom<&[u8]>>::Error> {
 		let time = Instant::now();
 
-		let ip = Ipv4Packet::new(buffer).ok_or(anyhow!("Packet too small."))?;
+		let ip = fetch_network_info(buffer)?;
 		let info = fetch_next_header_info(&ip)?;
 
 		Ok(Response {
-			origin: (ip.get_source(), info.source).into(),
+			origin: (info.origin.unwrap_or(ip.source), info.source).into(),
 			probe_id: info.destination,
 			kind: info.protocol,
-			time
+			time,
+			ip_id: ip.identification
 		})
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+	use pnet::packet::icmp::IcmpTypes;
+	use pnet::packet::icmp::destination_unreachable::IcmpCodes;
+	use pnet::packet::tcp::TcpFlags;
+	use super::{protocol_answer, Response, ResponseKind};
+	use crate::probes::sctp;
+
+	// IPv6 header followed by payload
+	fn ipv6(next: u8, source: &str, destination: &str, payload: &[u8]) -> Vec<u8> {
+		let mut packet = vec![0x60, 0, 0, 0];
+		packet.extend((payload.len() as u16).to_be_bytes());
+		packet.extend([next, 64]);
+		packet.extend(source.parse::<Ipv6Addr>().unwrap().octets());
+		packet.extend(destination.parse::<Ipv6Addr>().unwrap().octets());
+		packet.extend(payload);
+		packet
+	}
+
+	fn tcp(source: u16, destination: u16, flags: u8) -> Vec<u8> {
+		let mut header = vec![0u8; 20];
+		header[0..2].copy_from_slice(&source.to_be_bytes());
+		header[2..4].copy_from_slice(&destination.to_be_bytes());
+		header[12] = 5 << 4;
+		header[13] = flags;
+		header
+	}
+
+	#[test]
+	fn ipv6_tcp_through_extension_headers() {
+		let mut payload = vec![];
+		payload.extend([60, 0, 0, 0, 0, 0, 0, 0]); // hop-by-hop, then destination options
+		payload.extend([44, 1]); // 16 bytes of destination options, then fragment
+		payload.extend([0u8; 14]);
+		payload.extend([6, 0, 0, 1, 0, 0, 0, 42]); // first fragment, then TCP
+		payload.extend(tcp(443, 40001, (TcpFlags::SYN | TcpFlags::ACK) as u8));
+		let packet = ipv6(0, "2001:db8::2", "2001:db8::1", &payload);
+
+		let response = Response::try_from(&packet[..]).unwrap();
+		assert_eq!(response.origin, "[2001:db8::2]:443".parse::<SocketAddr>().unwrap());
+		assert_eq!(response.probe_id, 40001);
+		assert!(matches!(response.kind, ResponseKind::Tcp { flags, .. } if flags == TcpFlags::SYN | TcpFlags::ACK));
+		assert_eq!(response.ip_id, None);
+
+		// Later fragments do not carry ports
+		let mut fragment = payload.clone();
+		fragment[26] = 0x10;
+		assert!(Response::try_from(&ipv6(0, "2001:db8::2", "2001:db8::1", &fragment)[..]).is_err());
+		assert!(Response::try_from(&ipv6(0, "2001:db8::2", "2001:db8::1", &payload[..20])[..]).is_err());
+	}
+
+	#[test]
+	fn icmpv6_errors() {
+		let mut probe = vec![0u8; 8];
+		probe[0..2].copy_from_slice(&40005u16.to_be_bytes());
+		probe[2..4].copy_from_slice(&53u16.to_be_bytes());
+		let quoted = ipv6(17, "2001:db8::1", "2001:db8::2", &probe);
+
+		// Port unreachable from the target itself
+		let mut icmp = vec![1, 4, 0, 0, 0, 0, 0, 0];
+		icmp.extend(&quoted);
+		let response = Response::try_from(&ipv6(58, "2001:db8::2", "2001:db8::1", &icmp)[..]).unwrap();
+		assert_eq!(response.origin, "[2001:db8::2]:53".parse::<SocketAddr>().unwrap());
+		assert_eq!(response.probe_id, 40005);
+		assert!(matches!(response.kind, ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, IcmpCodes::DestinationPortUnreachable)));
+
+		// Administratively prohibited, sent by a router
+		icmp[1] = 1;
+		let response = Response::try_from(&ipv6(58, "2001:db8:ffff::1", "2001:db8::1", &icmp)[..]).unwrap();
+		assert_eq!(response.origin.ip(), "2001:db8::2".parse::<IpAddr>().unwrap());
+		assert!(matches!(response.kind, ResponseKind::Icmp(_, IcmpCodes::CommunicationAdministrativelyProhibited)));
+
+		// Unrecognized next header, the pointer takes the place of the unused bytes
+		icmp[0..8].copy_from_slice(&[4, 1, 0, 0, 0, 0, 0, 6]);
+		let response = Response::try_from(&ipv6(58, "2001:db8::2", "2001:db8::1", &icmp)[..]).unwrap();
+		assert!(matches!(response.kind, ResponseKind::Icmp(_, IcmpCodes::DestinationProtocolUnreachable)));
+
+		// Packet too big says nothing about the port
+		icmp[0..8].copy_from_slice(&[2, 0, 0, 0, 0, 0, 0x05, 0x00]);
+		assert!(Response::try_from(&ipv6(58, "2001:db8:ffff::1", "2001:db8::1", &icmp)[..]).is_err());
+
+		// Echo replies do not quote anything
+		icmp[0] = 129;
+		assert!(Response::try_from(&ipv6(58, "2001:db8::2", "2001:db8::1", &icmp)[..]).is_err());
+	}
+
+	#[test]
+	fn icmpv4_truncated_quote() {
+		// IPv4 header of the probe and the first 8 bytes of TCP, as most routers do
+		let mut quoted = vec![0x45, 0, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+		quoted.extend(&tcp(40000, 22, TcpFlags::SYN as u8)[..8]);
+		let mut icmp = vec![3, 13, 0, 0, 0, 0, 0, 0];
+		icmp.extend(&quoted);
+
+		let mut packet = vec![0x45, 0, 0, 20 + icmp.len() as u8, 0, 0, 0, 0, 64, 1, 0, 0, 192, 168, 0, 1, 10, 0, 0, 1];
+		packet.extend(&icmp);
+
+		let response = Response::try_from(&packet[..]).unwrap();
+		assert_eq!(response.origin, SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 22)));
+		assert_eq!(response.probe_id, 40000);
+		assert!(matches!(response.kind, ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, IcmpCodes::CommunicationAdministrativelyProhibited)));
+	}
+
+	#[test]
+	fn sctp_answers() {
+		let abort = sctp::packet(3868, 40010, sctp::ABORT, 1);
+		let response = Response::try_from(&ipv6(132, "2001:db8::2", "2001:db8::1", &abort)[..]).unwrap();
+		assert_eq!(response.origin, "[2001:db8::2]:3868".parse::<SocketAddr>().unwrap());
+		assert_eq!(response.probe_id, 40010);
+		assert!(matches!(response.kind, ResponseKind::Sctp(sctp::ABORT)));
+
+		// Protocol unreachable quoting the first 8 bytes of an INIT
+		let mut icmp = vec![1, 6, 0, 0, 0, 0, 0, 0];
+		icmp.extend(ipv6(132, "2001:db8::1", "2001:db8::2", &sctp::packet(40010, 3868, sctp::INIT, 1)[..8]));
+		let response = Response::try_from(&ipv6(58, "2001:db8::2", "2001:db8::1", &icmp)[..]).unwrap();
+		assert_eq!(response.origin.port(), 3868);
+		assert_eq!(response.probe_id, 40010);
+		assert!(Response::try_from(&ipv6(132, "2001:db8::2", "2001:db8::1", &abort[..12])[..]).is_err());
+	}
+
+	#[test]
+	fn ipv4_identification() {
+		let mut packet = vec![0x45, 0, 0, 40, 0x12, 0x34, 0, 0, 64, 6, 0, 0, 10, 0, 0, 9, 10, 0, 0, 1];
+		packet.extend(tcp(80, 40014, TcpFlags::RST as u8));
+
+		let response = Response::try_from(&packet[..]).unwrap();
+		assert_eq!(response.ip_id, Some(0x1234));
+		assert_eq!(response.probe_id, 40014);
+	}
+
+	#[test]
+	fn protocol_answers() {
+		// Protocol unreachable quoting a GRE probe
+		let quoted = vec![0x45, 0, 0, 20, 0, 0, 0, 0, 64, 47, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+		let mut icmp = vec![3, 2, 0, 0, 0, 0, 0, 0];
+		icmp.extend(&quoted);
+		let mut packet = vec![0x45, 0, 0, 20 + icmp.len() as u8, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1];
+		packet.extend(&icmp);
+
+		let (origin, kind) = protocol_answer(&packet).unwrap();
+		assert_eq!(origin, SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 47)));
+		assert!(matches!(kind, ResponseKind::Icmp(IcmpTypes::DestinationUnreachable, IcmpCodes::DestinationProtocolUnreachable)));
+
+		// Echo reply
+		packet[20] = 0;
+		let (origin, kind) = protocol_answer(&packet).unwrap();
+		assert_eq!(origin, SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 1)));
+		assert!(matches!(kind, ResponseKind::Protocol));
+
+		// Time exceeded, from a router
+		packet[20] = 11;
+		assert!(protocol_answer(&packet).is_err());
+
+		let (origin, _) = protocol_answer(&ipv6(132, "2001:db8::2", "2001:db8::1", &[0; 12])).unwrap();
+		assert_eq!(origin.port(), 132);
+	}
+}
@@ -0,0 +1,198 @@
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+use crate::iterators::{hosts, HostRange, Span};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+	ThisNetwork,
+	Multicast,
+	Reserved,
+	LimitedBroadcast,
+	Unspecified,
+	NetworkAddress,
+	BroadcastAddress
+}
+
+impl Display for Reason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", match self {
+			Reason::ThisNetwork => "\"this\" network",
+			Reason::Multicast => "multicast",
+			Reason::Reserved => "reserved",
+			Reason::LimitedBroadcast => "limited broadcast",
+			Reason::Unspecified => "unspecified",
+			Reason::NetworkAddress => "network address",
+			Reason::BroadcastAddress => "broadcast address"
+		})
+	}
+}
+
+// Special-use ranges that can't be a unicast destination, they don't overlap
+fn special_ranges() -> Vec<(HostRange, Reason)> {
+	vec![
+		(HostRange::new(Ipv4Addr::new(0, 0, 0, 0), Ipv4Addr::new(0, 255, 255, 255)), Reason::ThisNetwork),
+		(HostRange::new(Ipv4Addr::new(224, 0, 0, 0), Ipv4Addr::new(239, 255, 255, 255)), Reason::Multicast),
+		(HostRange::new(Ipv4Addr::new(240, 0, 0, 0), Ipv4Addr::new(255, 255, 255, 254)), Reason::Reserved),
+		(HostRange::from(Ipv4Addr::BROADCAST), Reason::LimitedBroadcast),
+		(HostRange::from(Ipv6Addr::UNSPECIFIED), Reason::Unspecified),
+		(HostRange::new(Ipv6Addr::new(0xff00, 0, 0, 0, 0, 0, 0, 0), Ipv6Addr::from(u128::MAX)), Reason::Multicast)
+	]
+}
+
+pub fn classify(addr: IpAddr) -> Option<Reason> {
+	special_ranges().into_iter().find(|(range, _)| range.contains(addr)).map(|(_, reason)| reason)
+}
+
+// Network and broadcast addresses of an IPv4 CIDR block
+// /31 and /32 blocks have none (RFC 3021), neither has anything that is not a CIDR block
+pub fn cidr_edges(spec: &str) -> Vec<(HostRange, Reason)> {
+	let block = match spec.split_once('/') {
+		Some((_, prefix)) if prefix.trim().parse::<u8>().is_ok_and(|p| p <= 30) => HostRange::try_from(spec),
+		_ => return vec![]
+	};
+
+	match block {
+		Ok(block) if !block.is_ipv6() => vec![
+			(HostRange::from(block.at(0)), Reason::NetworkAddress),
+			(HostRange::from(block.at(block.size() - 1)), Reason::BroadcastAddress)
+		],
+		_ => vec![]
+	}
+}
+
+// Addresses of a target that are not one of its own edges
+pub fn without_edges(ranges: &[HostRange], edges: &[(HostRange, Reason)]) -> Vec<HostRange> {
+	let edges = hosts::merge(edges.iter().map(|(edge, _)| *edge).collect());
+	hosts::subtract(hosts::merge(ranges.to_vec()), &edges).0
+}
+
+// Edges some other target names are scanned, like 10.0.0.255 in "-i 10.0.0.0/24 -i 10.0.0.255"
+// named holds what targets name without their own edges
+pub fn unnamed(edges: Vec<(HostRange, Reason)>, named: Vec<HostRange>) -> Vec<(HostRange, Reason)> {
+	let named = hosts::merge(named);
+	edges.into_iter().filter(|(edge, _)| !hosts::contains(&named, edge.at(0))).collect()
+}
+
+/*
+** Keeps addresses that should not be probed out of the targets
+** unless explicitly allowed, and remembers what was left out
+** shared with the thread reading streamed targets
+*/
+#[derive(Clone, Debug)]
+pub struct Safety {
+	allow: bool,
+	skipped: Arc<Mutex<Vec<(HostRange, Reason)>>>
+}
+
+impl Safety {
+	pub fn new(allow: bool) -> Self {
+		Self { allow, skipped: Arc::default() }
+	}
+
+	// Edges are the network and broadcast addresses of the CIDR blocks ranges come from
+	// special-use addresses win over edges so that each address has a single reason
+	pub fn filter(&self, ranges: Vec<HostRange>, edges: Vec<(HostRange, Reason)>) -> Vec<HostRange> {
+		let ranges = hosts::merge(ranges);
+		if self.allow {
+			return ranges;
+		}
+
+		let mut unsafe_ranges = special_ranges();
+		unsafe_ranges.extend(edges.into_iter().filter(|(edge, _)| classify(edge.at(0)).is_none()));
+
+		let mut skipped = self.skipped.lock().unwrap();
+		for (range, reason) in unsafe_ranges.iter() {
+			skipped.extend(hosts::intersect(&ranges, range).into_iter().map(|r| (r, *reason)));
+		}
+
+		let unsafe_ranges = hosts::merge(unsafe_ranges.into_iter().map(|(range, _)| range).collect());
+		hosts::subtract(ranges, &unsafe_ranges).0
+	}
+
+	pub fn allows(&self, addr: IpAddr) -> bool {
+		self.filter(vec![HostRange::from(addr)], vec![]).len() == 1
+	}
+
+	// Sorted, each range only once
+	pub fn skipped(&self) -> Vec<(HostRange, Reason)> {
+		let mut skipped = self.skipped.lock().unwrap().clone();
+		skipped.sort();
+		skipped.dedup();
+		skipped
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::net::{IpAddr, Ipv4Addr};
+	use super::{cidr_edges, classify, unnamed, without_edges, Reason, Safety};
+	use crate::iterators::HostRange;
+
+	#[test]
+	fn special_use_addresses() {
+		let class = |addr: &str| classify(addr.parse::<IpAddr>().unwrap());
+
+		assert_eq!(class("0.1.2.3"), Some(Reason::ThisNetwork));
+		assert_eq!(class("224.0.0.251"), Some(Reason::Multicast));
+		assert_eq!(class("250.0.0.1"), Some(Reason::Reserved));
+		assert_eq!(class("255.255.255.255"), Some(Reason::LimitedBroadcast));
+		assert_eq!(class("::"), Some(Reason::Unspecified));
+		assert_eq!(class("ff02::1"), Some(Reason::Multicast));
+		assert_eq!(class("10.0.0.0"), None);
+		assert_eq!(class("127.0.0.1"), None);
+		assert_eq!(class("2001:db8::"), None);
+	}
+
+	#[test]
+	fn network_and_broadcast_addresses() {
+		let edges: Vec<String> = cidr_edges(" 10.1.2.3/24").iter().map(|(range, reason)| format!("{range} {reason}")).collect();
+		assert_eq!(edges, ["10.1.2.0 network address", "10.1.2.255 broadcast address"]);
+
+		assert!(cidr_edges("10.0.0.0/31").is_empty());
+		assert!(cidr_edges("10.0.0.0").is_empty());
+		assert!(cidr_edges("10.0.0.0-5").is_empty());
+		assert!(cidr_edges("2001:db8::/120").is_empty());
+	}
+
+	#[test]
+	fn filter_and_report() {
+		let safety = Safety::new(false);
+		let targets = vec![HostRange::try_from("10.0.0.0/30").unwrap(), HostRange::new(Ipv4Addr::new(223, 255, 255, 255), Ipv4Addr::new(224, 0, 0, 1))];
+		let kept = safety.filter(targets.clone(), cidr_edges("10.0.0.0/30"));
+
+		assert_eq!(kept, vec![
+			HostRange::new(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)),
+			HostRange::from(Ipv4Addr::new(223, 255, 255, 255))
+		]);
+		assert!(!safety.allows(IpAddr::V4(Ipv4Addr::new(239, 1, 1, 1))));
+		assert!(safety.allows(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0))));
+
+		let skipped: Vec<String> = safety.skipped().iter().map(|(range, reason)| format!("{range} {reason}")).collect();
+		assert_eq!(skipped, ["10.0.0.0 network address", "10.0.0.3 broadcast address", "224.0.0.0-224.0.0.1 multicast", "239.1.1.1 multicast"]);
+
+		let unsafe_targets = Safety::new(true);
+		assert_eq!(unsafe_targets.filter(targets.clone(), cidr_edges("10.0.0.0/30")), crate::iterators::hosts::merge(targets));
+		assert!(unsafe_targets.skipped().is_empty());
+	}
+
+	#[test]
+	fn edges_named_by_other_targets() {
+		let range = |spec: &str| vec![HostRange::try_from(spec).unwrap()];
+		let targets = |specs: &[&str]| {
+			let (mut edges, mut named) = (vec![], vec![]);
+			for spec in specs.iter() {
+				let target_edges = cidr_edges(spec);
+				named.extend(without_edges(&range(spec), &target_edges));
+				edges.extend(target_edges);
+			}
+			unnamed(edges, named).iter().map(|(range, _)| range.to_string()).collect::<Vec<_>>()
+		};
+
+		assert_eq!(targets(&["10.0.0.0/24", "10.0.0.255"]), ["10.0.0.0"]);
+		assert_eq!(targets(&["10.0.0.0/24", "10.0.0.0/24"]), ["10.0.0.0", "10.0.0.255", "10.0.0.0", "10.0.0.255"]);
+		assert_eq!(targets(&["10.0.0.0/24", "10.0.0.0/16"]), ["10.0.0.0", "10.0.0.0", "10.0.255.255"]);
+		assert_eq!(targets(&["10.0.0.0/24", "10.0.1.0/24"]), ["10.0.0.0", "10.0.0.255", "10.0.1.0", "10.0.1.255"]);
+	}
+}
@@ -0,0 +1,95 @@
+// Chunk types, RFC 9260 section 3.2
+pub const INIT: u8 = 1;
+pub const INIT_ACK: u8 = 2;
+pub const ABORT: u8 = 6;
+pub const COOKIE_ECHO: u8 = 10;
+
+// Castagnoli polynomial, reflected
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+
+pub fn crc32c(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for byte in data.iter() {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (CRC32C_POLY & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
+
+/*
+** Common header followed by a single chunk
+** INIT starts an association, the verification tag must then be 0
+** COOKIE ECHO carries a cookie no endpoint ever gave us
+** the checksum is over the whole packet and does not involve IP addresses
+*/
+pub fn packet(source: u16, destination: u16, chunk: u8, tag: u32) -> Vec<u8> {
+	let mut packet = Vec::with_capacity(32);
+	packet.extend(source.to_be_bytes());
+	packet.extend(destination.to_be_bytes());
+
+	match chunk {
+		INIT => {
+			packet.extend(0u32.to_be_bytes());
+			packet.extend([0; 4]); // checksum
+			packet.extend([INIT, 0]);
+			packet.extend(20u16.to_be_bytes());
+			packet.extend(tag.max(1).to_be_bytes()); // initiate tag, 0 is not allowed
+			packet.extend(65535u32.to_be_bytes()); // advertised receiver window
+			packet.extend(10u16.to_be_bytes()); // outbound streams
+			packet.extend(2048u16.to_be_bytes()); // inbound streams
+			packet.extend(tag.to_be_bytes()); // initial TSN
+		},
+		_ => {
+			packet.extend(tag.to_be_bytes());
+			packet.extend([0; 4]); // checksum
+			packet.extend([chunk, 0]);
+			packet.extend(8u16.to_be_bytes());
+			packet.extend([0; 4]); // cookie
+		}
+	}
+
+	// Sent least significant byte first, RFC 9260 appendix A
+	let checksum = crc32c(&packet);
+	packet[8..12].copy_from_slice(&checksum.to_le_bytes());
+	packet
+}
+
+// Ports and type of the first chunk
+pub fn parse(payload: &[u8]) -> Option<(u16, u16, u8)> {
+	match payload {
+		[a, b, c, d, _, _, _, _, _, _, _, _, chunk, ..] => Some((u16::from_be_bytes([*a, *b]), u16::from_be_bytes([*c, *d]), *chunk)),
+		_ => None
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{crc32c, packet, parse, COOKIE_ECHO, INIT};
+
+	#[test]
+	fn crc32c_check_value() {
+		assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+		assert_eq!(crc32c(b""), 0);
+	}
+
+	#[test]
+	fn init_and_cookie_echo() {
+		let init = packet(40010, 3868, INIT, 0xdead_beef);
+		assert_eq!(init.len(), 32);
+		assert_eq!(parse(&init), Some((40010, 3868, INIT)));
+		assert_eq!(&init[4..8], &[0; 4]);
+
+		// Checksum of the packet with a zeroed checksum field
+		let mut zeroed = init.clone();
+		zeroed[8..12].copy_from_slice(&[0; 4]);
+		assert_eq!(&init[8..12], &crc32c(&zeroed).to_le_bytes());
+
+		let cookie = packet(40011, 2905, COOKIE_ECHO, 7);
+		assert_eq!(cookie.len(), 20);
+		assert_eq!(parse(&cookie), Some((40011, 2905, COOKIE_ECHO)));
+		assert_eq!(&cookie[4..8], &7u32.to_be_bytes());
+		assert_eq!(parse(&cookie[..12]), None);
+	}
+}